@@ -1,14 +1,21 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use ggez::{
     graphics,
     nalgebra::Point2,
 };
 use nphysics2d::nalgebra::base::Vector2;
+use nphysics2d::nalgebra::Isometry2;
+use nphysics2d::ncollide2d::shape::ShapeHandle;
 use nphysics2d::object::{DefaultBodyHandle, DefaultColliderHandle};
-use specs::{NullStorage, VecStorage};
+use serde::Deserialize;
+use specs::{Entity, NullStorage, VecStorage};
 use specs::Component;
 
+use crate::engine::animation::{AnimationGraph, AnimationParameters};
+use crate::engine::assets::{AnimationClip, LoopMode, TextureHandle};
+
 #[derive(Clone, Copy, Debug)]
 pub struct Body {
     pub rigid_body_handle: DefaultBodyHandle,
@@ -22,7 +29,11 @@ impl Component for Body {
 #[derive(Clone, Copy, Debug)]
 pub struct FeetSensor {
     pub collider_handle: DefaultColliderHandle,
-    pub on_floor: bool
+    pub on_floor: bool,
+    /// Velocity of the body stood on, carried over to whoever stands on it.
+    pub ground_velocity: Vector2<f32>,
+    /// Surface normal of the ground stood on.
+    pub ground_normal: Vector2<f32>,
 }
 
 impl Component for FeetSensor {
@@ -31,13 +42,172 @@ impl Component for FeetSensor {
 
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Jumper {
+    /// Whether holding the jump button still pushes the current jump higher.
     pub jump_active: bool,
+    /// Set once a jump starts and cleared on landing, so the jumper isn't snapped to the ground.
+    pub jumping: bool,
+    /// Whether the jump button was held on the previous tick.
+    pub held: bool,
+    /// Whether down was held on the previous tick.
+    pub down_held: bool,
+    /// Ticks left in which a jump can still start after walking off a ledge.
+    pub coyote_ticks: u32,
+    /// Ticks left in which a jump press made just before landing is still remembered.
+    pub buffer_ticks: u32,
 }
 
 impl Component for Jumper {
     type Storage = VecStorage<Self>;
 }
 
+/// Move the player is in the middle of, beyond running and jumping.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpecialMove {
+    None,
+    /// Breaks blocks it lands on and bounces off spiky enemies.
+    SpinJump,
+    GroundPound { ticks: u32 },
+    /// Sliding down a wall facing away from the given wall normal.
+    WallSlide { normal_x: f32 },
+    /// Holding on to a vine or ladder, moving in any direction without gravity.
+    Climb,
+}
+
+impl Default for SpecialMove {
+    fn default() -> Self {
+        SpecialMove::None
+    }
+}
+
+impl Component for SpecialMove {
+    type Storage = VecStorage<Self>;
+}
+
+/// Lets a body crouch by swapping its collider for a smaller one.
+#[derive(Clone)]
+pub struct Crouch {
+    pub crouching: bool,
+    pub standing_shape: ShapeHandle<f32>,
+    pub crouching_shape: ShapeHandle<f32>,
+    /// Where the crouching collider sits on the body, so its bottom stays where it was.
+    pub crouching_offset: Vector2<f32>,
+}
+
+impl Crouch {
+    /// How much higher the top of the body reaches when standing than when crouching.
+    pub fn clearance(&self) -> f32 {
+        let standing = self.standing_shape.aabb(&Isometry2::identity());
+        let crouching = self.crouching_shape.aabb(&Isometry2::new(self.crouching_offset, 0.0));
+
+        standing.maxs().y - crouching.maxs().y
+    }
+}
+
+impl Component for Crouch {
+    type Storage = VecStorage<Self>;
+}
+
+/// Blocks broken by spin jumps and ground pounds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Breakable;
+
+impl Component for Breakable {
+    type Storage = NullStorage<Self>;
+}
+
+/// Enemies that hurt when stomped on, unless spin jumped on.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Spiky;
+
+impl Component for Spiky {
+    type Storage = NullStorage<Self>;
+}
+
+/// Objects that can be picked up by holding run, and thrown by letting go of it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Carryable;
+
+impl Component for Carryable {
+    type Storage = NullStorage<Self>;
+}
+
+/// Lets a body pick up carryable objects.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Carrier {
+    pub held: Option<Entity>,
+}
+
+impl Component for Carrier {
+    type Storage = VecStorage<Self>;
+}
+
+/// Marks a body that is inside a water zone.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InWater;
+
+impl Component for InWater {
+    type Storage = NullStorage<Self>;
+}
+
+/// Swims back and forth while in water, like a Cheep-Cheep.
+#[derive(Clone, Copy, Debug)]
+pub struct SwimAI {
+    /// -1 swimming left, 1 swimming right.
+    pub direction: f32,
+    /// Ticks since the last turn.
+    pub ticks: u32,
+}
+
+impl Default for SwimAI {
+    fn default() -> Self {
+        Self {
+            direction: -1.0,
+            ticks: 0,
+        }
+    }
+}
+
+impl Component for SwimAI {
+    type Storage = VecStorage<Self>;
+}
+
+/// Multiplier of the world gravity for a single body, as gravity is shared by the whole physics world.
+#[derive(Clone, Copy, Debug)]
+pub struct GravityScale(pub f32);
+
+impl Default for GravityScale {
+    fn default() -> Self {
+        GravityScale(1.0)
+    }
+}
+
+impl Component for GravityScale {
+    type Storage = VecStorage<Self>;
+}
+
+/// Moves a kinematic body by integrating its velocity and sweeping it through the level,
+/// instead of leaving it to the physics world.
+#[derive(Clone, Copy, Debug)]
+pub struct CharacterController {
+    /// Velocity relative to the ground stood on.
+    pub velocity: Vector2<f32>,
+    /// Whether gravity pulls on the character, off while it's frozen or sliding through a pipe.
+    pub gravity: bool,
+}
+
+impl Default for CharacterController {
+    fn default() -> Self {
+        Self {
+            velocity: Vector2::zeros(),
+            gravity: true,
+        }
+    }
+}
+
+impl Component for CharacterController {
+    type Storage = VecStorage<Self>;
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct Transform {
     pub position: Point2<f32>,
@@ -49,8 +219,44 @@ impl Component for Transform {
     type Storage = VecStorage<Self>;
 }
 
+/// Where an entity was at the end of the previous tick, to draw it in between ticks.
+#[derive(Clone, Copy, Debug)]
+pub struct PreviousTransform {
+    pub position: Point2<f32>,
+    pub rotation: f32,
+}
+
+impl Component for PreviousTransform {
+    type Storage = VecStorage<Self>;
+}
+
+/// Where a sprite is drawn relative to the others. Sprites without one are drawn at depth 0,
+/// along with the player.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RenderLayer {
+    /// Higher depths are drawn over lower ones, so foreground tiles can cover the player.
+    pub depth: i32,
+    /// Fraction of the camera movement the layer scrolls with, below 1 for distant backgrounds.
+    pub parallax: f32,
+}
+
+impl Default for RenderLayer {
+    fn default() -> Self {
+        Self {
+            depth: 0,
+            parallax: 1.0,
+        }
+    }
+}
+
+impl Component for RenderLayer {
+    type Storage = VecStorage<Self>;
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct Sprite {
+    pub texture: TextureHandle,
     pub src: graphics::Rect,
     pub width: f32,
     pub height: f32,
@@ -61,11 +267,17 @@ impl Component for Sprite {
     type Storage = VecStorage<Self>;
 }
 
+/// Plays the clips of an animation graph, moving between its states as the parameters change.
 #[derive(Clone, Debug, Default)]
 pub struct Animation {
-    pub current_state: AnimationStates,
-    pub animations: HashMap<AnimationStates, AnimationParams>,
-    pub speed: f32,
+    pub graph: Arc<AnimationGraph>,
+    pub current_state: String,
+    /// State whose clip was last played, to restart clips when the state changes.
+    pub playing: Option<String>,
+    pub parameters: AnimationParameters,
+    /// Clip playback by state.
+    pub animations: HashMap<String, AnimationParams>,
+    /// Playback rate of states following a parameter, per unit of it.
     pub speed_factor: f32,
 }
 
@@ -73,27 +285,95 @@ impl Component for Animation {
     type Storage = VecStorage<Self>;
 }
 
-#[derive(Clone, Copy, Debug, Default)]
+/// Playback of an animation clip.
+#[derive(Clone, Debug, Default)]
 pub struct AnimationParams {
-    pub frame: f32,
-    pub start_frame: u32,
-    pub frame_count: u32,
+    pub clip: AnimationClip,
+    /// Index of the clip frame shown.
+    pub frame: usize,
+    /// Seconds the frame has been shown for.
+    pub elapsed: f32,
+    /// Whether a ping-pong clip is on its way back.
+    pub reversed: bool,
+    pub finished: bool,
 }
 
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
-pub enum AnimationStates {
-    Idle,
-    Moving,
-    Jumping,
-    Drag
-}
+impl AnimationParams {
+    pub fn new(clip: AnimationClip) -> Self {
+        Self {
+            clip,
+            ..Self::default()
+        }
+    }
 
-impl Default for AnimationStates {
-    fn default() -> Self {
-        AnimationStates::Idle
+    pub fn restart(&mut self) {
+        self.frame = 0;
+        self.elapsed = 0.0;
+        self.reversed = false;
+        self.finished = false;
+    }
+
+    /// Advances playback by `delta` seconds, returning whether a clip played once has just finished.
+    pub fn advance(&mut self, delta: f32) -> bool {
+        if self.finished || self.clip.frames.is_empty() {
+            return false;
+        }
+
+        self.elapsed += delta;
+
+        while self.elapsed >= self.clip.durations[self.frame] {
+            self.elapsed -= self.clip.durations[self.frame];
+
+            if !self.next_frame() {
+                self.elapsed = 0.0;
+                self.finished = true;
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Moves to the following frame, or returns false at the end of a clip played once.
+    fn next_frame(&mut self) -> bool {
+        let last = self.clip.frames.len() - 1;
+
+        match self.clip.mode {
+            LoopMode::Loop => self.frame = if self.frame == last { 0 } else { self.frame + 1 },
+            LoopMode::Once if self.frame == last => return false,
+            LoopMode::Once => self.frame += 1,
+            LoopMode::PingPong if last == 0 => {}
+            LoopMode::PingPong if self.reversed => {
+                if self.frame == 0 {
+                    self.reversed = false;
+                    self.frame = 1;
+                } else {
+                    self.frame -= 1;
+                }
+            }
+            LoopMode::PingPong => {
+                if self.frame == last {
+                    self.reversed = true;
+                    self.frame = last - 1;
+                } else {
+                    self.frame += 1;
+                }
+            }
+        }
+
+        true
     }
 }
 
+/// A puff of dust kicked up by a footstep, gone after `ticks` more ticks.
+#[derive(Clone, Copy, Debug)]
+pub struct Dust {
+    pub ticks: u32,
+}
+
+impl Component for Dust {
+    type Storage = VecStorage<Self>;
+}
 
 #[derive(Clone, Copy, Debug, Default)]
 pub struct CameraTarget;
@@ -115,4 +395,301 @@ pub struct BasicAI;
 
 impl Component for BasicAI {
     type Storage = NullStorage<Self>;
-}
\ No newline at end of file
+}
+
+/// Marks an entity that has been killed and no longer responds to control.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Killed;
+
+impl Component for Killed {
+    type Storage = NullStorage<Self>;
+}
+
+/// Progress of a killed player through the death animation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeathSequence {
+    pub ticks: u32,
+}
+
+impl Component for DeathSequence {
+    type Storage = VecStorage<Self>;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Checkpoint {
+    pub activated: bool,
+}
+
+impl Component for Checkpoint {
+    type Storage = VecStorage<Self>;
+}
+
+/// Flagpole at the end of a level.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Goal {
+    pub bottom: f32,
+    pub height: f32,
+    /// Horizontal position of the castle door the player walks to.
+    pub castle_x: f32,
+}
+
+impl Component for Goal {
+    type Storage = VecStorage<Self>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GoalPhase {
+    Walk,
+    Tally,
+    Done,
+}
+
+/// Scripted control of a player who reached the goal.
+#[derive(Clone, Copy, Debug)]
+pub struct LevelComplete {
+    pub phase: GoalPhase,
+    pub castle_x: f32,
+}
+
+impl Component for LevelComplete {
+    type Storage = VecStorage<Self>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerState {
+    Small,
+    Super,
+}
+
+impl Default for PowerState {
+    fn default() -> Self {
+        PowerState::Small
+    }
+}
+
+impl Component for PowerState {
+    type Storage = VecStorage<Self>;
+}
+
+/// Direction the player travels to enter a pipe.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum PipeDirection {
+    Down,
+    Up,
+    Left,
+    Right,
+}
+
+impl PipeDirection {
+    pub fn vector(self) -> Vector2<f32> {
+        match self {
+            PipeDirection::Down => Vector2::new(0.0, -1.0),
+            PipeDirection::Up => Vector2::new(0.0, 1.0),
+            PipeDirection::Left => Vector2::new(-1.0, 0.0),
+            PipeDirection::Right => Vector2::new(1.0, 0.0),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct WarpDestination {
+    /// Level file holding the target area, or the current level when missing.
+    #[serde(default)]
+    pub level: Option<String>,
+    pub area: String,
+    /// Id of the pipe the player comes out of.
+    pub pipe: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Pipe {
+    pub direction: PipeDirection,
+    /// Center of the pipe mouth.
+    pub opening: Point2<f32>,
+    pub destination: Option<WarpDestination>,
+}
+
+impl Component for Pipe {
+    type Storage = VecStorage<Self>;
+}
+
+/// Player sliding into a pipe on the way to its destination.
+#[derive(Clone, Debug)]
+pub struct PipeTransit {
+    pub direction: PipeDirection,
+    pub destination: WarpDestination,
+    pub ticks: u32,
+}
+
+impl Component for PipeTransit {
+    type Storage = VecStorage<Self>;
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(tag = "kind")]
+pub enum PlatformPath {
+    /// Travels `dx`, `dy` away from its origin and stops there.
+    Linear { dx: f32, dy: f32, speed: f32 },
+    /// Travels between its origin and `dx`, `dy` away from it.
+    BackAndForth { dx: f32, dy: f32, speed: f32 },
+    /// Circles around its origin, `speed` being in radians per second.
+    Circular { radius: f32, speed: f32 },
+    /// Stays put until stood on, then falls after `delay` ticks.
+    Falling { delay: u32 },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MovingPlatform {
+    pub path: PlatformPath,
+    pub origin: Point2<f32>,
+    /// Ticks since the platform started moving.
+    pub ticks: u32,
+    /// Whether a falling platform has been stood on.
+    pub triggered: bool,
+}
+
+impl Component for MovingPlatform {
+    type Storage = VecStorage<Self>;
+}
+
+/// Player dropping through the one-way platform below it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DropThrough {
+    pub ticks: u32,
+}
+
+impl Component for DropThrough {
+    type Storage = VecStorage<Self>;
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum SlopeAngle {
+    /// 22.5 degrees.
+    Gentle,
+    /// 45 degrees.
+    Steep,
+}
+
+impl SlopeAngle {
+    pub fn radians(self) -> f32 {
+        match self {
+            SlopeAngle::Gentle => std::f32::consts::PI / 8.0,
+            SlopeAngle::Steep => std::f32::consts::PI / 4.0,
+        }
+    }
+}
+
+/// Which way a slope rises, going from left to right.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum SlopeDirection {
+    Up,
+    Down,
+}
+
+#[cfg(test)]
+mod tests {
+    use ggez::graphics::Rect;
+
+    use crate::engine::assets::SpriteFrame;
+
+    use super::*;
+
+    /// A clip of `count` frames shown for a second each.
+    fn clip(count: usize, mode: LoopMode) -> AnimationClip {
+        let frame = SpriteFrame {
+            texture: TextureHandle::default(),
+            src: Rect::new(0.0, 0.0, 1.0, 1.0),
+        };
+
+        AnimationClip {
+            frames: vec![frame; count],
+            durations: vec![1.0; count],
+            mode,
+        }
+    }
+
+    /// Frames shown over `steps` seconds, one second at a time.
+    fn frames_shown(params: &mut AnimationParams, steps: usize) -> Vec<usize> {
+        (0..steps)
+            .map(|_| {
+                params.advance(1.0);
+                params.frame
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loop_wraps_around_to_the_first_frame() {
+        let mut params = AnimationParams::new(clip(3, LoopMode::Loop));
+
+        assert_eq!(frames_shown(&mut params, 5), vec![1, 2, 0, 1, 2]);
+        assert!(!params.finished);
+    }
+
+    #[test]
+    fn ping_pong_turns_around_without_repeating_the_ends() {
+        let mut params = AnimationParams::new(clip(3, LoopMode::PingPong));
+
+        assert_eq!(frames_shown(&mut params, 6), vec![1, 2, 1, 0, 1, 2]);
+        assert!(!params.finished);
+    }
+
+    #[test]
+    fn once_stops_on_the_last_frame() {
+        let mut params = AnimationParams::new(clip(3, LoopMode::Once));
+
+        assert!(!params.advance(1.0));
+        assert!(params.advance(2.0));
+        assert_eq!(params.frame, 2);
+        assert!(params.finished);
+
+        // Only the tick it ends on reports it.
+        assert!(!params.advance(1.0));
+        assert_eq!(params.frame, 2);
+    }
+
+    #[test]
+    fn advance_can_skip_several_frames() {
+        let mut params = AnimationParams::new(clip(4, LoopMode::Loop));
+
+        params.advance(2.5);
+
+        assert_eq!(params.frame, 2);
+        assert!((params.elapsed - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn one_frame_clips_stay_on_their_frame() {
+        for &mode in [LoopMode::Loop, LoopMode::PingPong].iter() {
+            let mut params = AnimationParams::new(clip(1, mode));
+
+            assert_eq!(frames_shown(&mut params, 3), vec![0, 0, 0]);
+            assert!(!params.finished);
+        }
+
+        let mut params = AnimationParams::new(clip(1, LoopMode::Once));
+
+        assert!(!params.advance(0.5));
+        assert!(params.advance(0.5));
+        assert_eq!(params.frame, 0);
+    }
+
+    #[test]
+    fn two_frame_ping_pong_alternates() {
+        let mut params = AnimationParams::new(clip(2, LoopMode::PingPong));
+
+        assert_eq!(frames_shown(&mut params, 4), vec![1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn restart_plays_from_the_start() {
+        let mut params = AnimationParams::new(clip(2, LoopMode::Once));
+
+        params.advance(5.0);
+        params.restart();
+
+        assert_eq!(params.frame, 0);
+        assert!(!params.finished);
+        assert!(!params.advance(0.5));
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ggez::{Context, filesystem, GameError, GameResult};
+use serde::Deserialize;
+
+use super::assets::TextureIndex;
+
+const ANIMATIONS_PATH: &str = "/animations.json";
+
+/// Values gameplay systems set for animation graphs to pick states by, with booleans stored as 0 or 1.
+#[derive(Clone, Debug, Default)]
+pub struct AnimationParameters(HashMap<&'static str, f32>);
+
+impl AnimationParameters {
+    pub fn set(&mut self, name: &'static str, value: f32) {
+        self.0.insert(name, value);
+    }
+
+    pub fn set_bool(&mut self, name: &'static str, value: bool) {
+        self.set(name, if value { 1.0 } else { 0.0 });
+    }
+
+    /// The value of a parameter, 0 until a system sets it.
+    pub fn get(&self, name: &str) -> f32 {
+        self.0.get(name).copied().unwrap_or(0.0)
+    }
+
+    pub fn get_bool(&self, name: &str) -> bool {
+        self.get(name) != 0.0
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Condition {
+    Is { parameter: String, is: bool },
+    Above { parameter: String, above: f32 },
+    Below { parameter: String, below: f32 },
+    /// Whether the clip of the current state has played to its end.
+    Finished { finished: bool },
+}
+
+impl Condition {
+    pub fn holds(&self, parameters: &AnimationParameters, finished: bool) -> bool {
+        match self {
+            Condition::Is { parameter, is } => parameters.get_bool(parameter) == *is,
+            Condition::Above { parameter, above } => parameters.get(parameter) > *above,
+            Condition::Below { parameter, below } => parameters.get(parameter) < *below,
+            Condition::Finished { finished: expected } => finished == *expected,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Transition {
+    /// States the transition can be taken from, any state when left empty.
+    #[serde(default)]
+    pub from: Vec<String>,
+    pub to: String,
+    #[serde(default)]
+    pub when: Vec<Condition>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AnimationState {
+    /// Atlas tag of the clip played, under the animation set, the state name when left out.
+    pub clip: Option<String>,
+    /// Parameter the playback rate follows, scaled by the tuned speed factor, rather than the authored timing.
+    pub rate: Option<String>,
+    /// Events raised as frames of the clip come up, by frame index.
+    pub events: HashMap<usize, Vec<String>>,
+}
+
+/// States of an animation set and the transitions between them.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AnimationGraph {
+    pub initial: String,
+    pub states: HashMap<String, AnimationState>,
+    /// Checked in order every tick, the first one that applies picks the state.
+    #[serde(default)]
+    pub transitions: Vec<Transition>,
+}
+
+impl AnimationGraph {
+    /// The state to move to from `current`, or none to stay in it.
+    pub fn next_state<'a>(&'a self, current: &str, parameters: &AnimationParameters, finished: bool) -> Option<&'a str> {
+        self.transitions
+            .iter()
+            .filter(|transition| transition.from.is_empty() || transition.from.iter().any(|from| from == current))
+            .find(|transition| transition.when.iter().all(|condition| condition.holds(parameters, finished)))
+            .map(|transition| transition.to.as_str())
+            .filter(|&to| to != current)
+    }
+
+    /// Checks that every state the graph refers to exists, and has a clip tagged in the atlas.
+    fn validate(&self, name: &str, textures: &TextureIndex) -> GameResult<()> {
+        let targets = self.transitions.iter().flat_map(|transition| transition.from.iter().chain(Some(&transition.to)));
+
+        for state in Some(&self.initial).into_iter().chain(targets) {
+            if !self.states.contains_key(state) {
+                return Err(GameError::ResourceLoadError(format!(
+                    "Animation graph {} has no state {}",
+                    name, state
+                )));
+            }
+        }
+
+        for (state, state_data) in self.states.iter() {
+            let clip = state_data.clip.as_deref().unwrap_or(state);
+
+            if textures.clip(name, clip).is_none() {
+                return Err(GameError::ResourceLoadError(format!(
+                    "Animation graph {} has no clip {} for state {}",
+                    name, clip, state
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Animation graphs by animation set, as listed in `resources/animations.json`.
+#[derive(Clone, Debug, Default)]
+pub struct AnimationGraphs(HashMap<String, Arc<AnimationGraph>>);
+
+impl AnimationGraphs {
+    /// Loads the graphs of every animation set, whose clips are looked up in `textures`.
+    pub fn load(ctx: &mut Context, textures: &TextureIndex) -> GameResult<Self> {
+        let file = filesystem::open(ctx, ANIMATIONS_PATH)?;
+        let graphs: HashMap<String, AnimationGraph> = serde_json::from_reader(file).map_err(|e| {
+            GameError::ResourceLoadError(format!("Failed to parse animations: {}", e))
+        })?;
+
+        for (name, graph) in graphs.iter() {
+            graph.validate(name, textures)?;
+        }
+
+        Ok(Self(graphs.into_iter().map(|(name, graph)| (name, Arc::new(graph))).collect()))
+    }
+
+    pub fn get(&self, set: &str) -> Option<Arc<AnimationGraph>> {
+        self.0.get(set).cloned()
+    }
+}
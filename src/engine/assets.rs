@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use ggez::{
+    Context,
+    filesystem,
+    GameError,
+    GameResult,
+    graphics::{FilterMode, Image, Rect, spritebatch::SpriteBatch},
+};
+use serde::{
+    de::{MapAccess, SeqAccess, Visitor},
+    Deserialize,
+    Deserializer,
+};
+
+const TEXTURES_PATH: &str = "/textures.json";
+
+/// Milliseconds a frame is shown for when the atlas doesn't say, Aseprite's default.
+const DEFAULT_FRAME_DURATION: f32 = 100.0;
+
+/// Separates the animation set from the clip in Aseprite tag names, as in `mario/Moving`.
+const TAG_SEPARATOR: char = '/';
+
+/// Refers to a texture loaded into `Textures`. The default handle is the first texture listed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TextureHandle(usize);
+
+#[derive(Clone, Debug, Deserialize)]
+struct TextureEntry {
+    name: String,
+    path: String,
+    /// Atlas file naming the frames on the texture, as exported by Aseprite or TexturePacker in either
+    /// the hash or the array format.
+    #[serde(default)]
+    atlas: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct AtlasData {
+    frames: AtlasFrames,
+    #[serde(default)]
+    meta: AtlasMeta,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct AtlasFrame {
+    frame: PixelRect,
+    /// Milliseconds the frame is shown for when animated.
+    #[serde(default)]
+    duration: Option<f32>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct NamedAtlasFrame {
+    filename: String,
+    #[serde(flatten)]
+    frame: AtlasFrame,
+}
+
+/// Atlas frames in the order they're listed, as Aseprite tags refer to frames by position.
+#[derive(Clone, Debug)]
+struct AtlasFrames(Vec<(String, AtlasFrame)>);
+
+impl<'de> Deserialize<'de> for AtlasFrames {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FramesVisitor;
+
+        impl<'de> Visitor<'de> for FramesVisitor {
+            type Value = AtlasFrames;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map or a list of atlas frames")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut frames = Vec::new();
+
+                while let Some(entry) = map.next_entry()? {
+                    frames.push(entry);
+                }
+
+                Ok(AtlasFrames(frames))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut frames = Vec::new();
+
+                while let Some(NamedAtlasFrame { filename, frame }) = seq.next_element()? {
+                    frames.push((filename, frame));
+                }
+
+                Ok(AtlasFrames(frames))
+            }
+        }
+
+        deserializer.deserialize_any(FramesVisitor)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct AtlasMeta {
+    #[serde(default, rename = "frameTags")]
+    frame_tags: Vec<FrameTag>,
+}
+
+/// A tagged range of frames in an Aseprite export.
+#[derive(Clone, Debug, Deserialize)]
+struct FrameTag {
+    name: String,
+    from: usize,
+    to: usize,
+    #[serde(default)]
+    direction: TagDirection,
+    /// How many times the tag plays, written as a string by Aseprite and left out to loop forever.
+    #[serde(default)]
+    repeat: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum TagDirection {
+    Forward,
+    Reverse,
+    Pingpong,
+    /// Ping-pongs, starting from the last frame.
+    PingpongReverse,
+}
+
+impl Default for TagDirection {
+    fn default() -> Self {
+        TagDirection::Forward
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+struct PixelRect {
+    x: f32,
+    y: f32,
+    w: f32,
+    h: f32,
+}
+
+/// Part of a texture to draw, with `src` in fractions of the texture size as sprite batches expect.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpriteFrame {
+    pub texture: TextureHandle,
+    pub src: Rect,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoopMode {
+    Loop,
+    /// Plays forwards then backwards, without repeating the frames at either end.
+    PingPong,
+    /// Stops on the last frame.
+    Once,
+}
+
+impl Default for LoopMode {
+    fn default() -> Self {
+        LoopMode::Loop
+    }
+}
+
+/// Frames of an animation with how long each is shown for, imported from an Aseprite tag.
+#[derive(Clone, Debug, Default)]
+pub struct AnimationClip {
+    pub frames: Vec<SpriteFrame>,
+    /// Seconds each frame is shown for.
+    pub durations: Vec<f32>,
+    pub mode: LoopMode,
+}
+
+/// Atlas frames and animation clips by name, for level data and entity builders to refer to them by.
+#[derive(Clone, Debug, Default)]
+pub struct TextureIndex {
+    frames: HashMap<String, SpriteFrame>,
+    /// Clips by animation set, then by name.
+    clips: HashMap<String, HashMap<String, AnimationClip>>,
+}
+
+impl TextureIndex {
+    pub fn frame(&self, name: &str) -> Option<SpriteFrame> {
+        self.frames.get(name).copied()
+    }
+
+    /// Checks that the atlas has every one of the frames.
+    pub fn check_frames(&self, names: &[&str]) -> GameResult<()> {
+        match names.iter().find(|name| !self.frames.contains_key(**name)) {
+            Some(name) => Err(GameError::ResourceLoadError(format!("Atlas has no sprite frame {}", name))),
+            None => Ok(()),
+        }
+    }
+
+    /// The clip tagged `<set>/<name>`.
+    pub fn clip(&self, set: &str, name: &str) -> Option<&AnimationClip> {
+        self.clips.get(set).and_then(|clips| clips.get(name))
+    }
+
+    fn add_atlas(&mut self, texture: TextureHandle, atlas: AtlasData, width: f32, height: f32) -> GameResult<()> {
+        let mut frames = Vec::new();
+
+        for (name, AtlasFrame { frame, duration }) in atlas.frames.0 {
+            let src = Rect::new(frame.x / width, frame.y / height, frame.w / width, frame.h / height);
+            let sprite_frame = SpriteFrame { texture, src };
+            let duration = duration.unwrap_or(DEFAULT_FRAME_DURATION).max(1.0) / 1000.0;
+
+            frames.push((sprite_frame, duration));
+            self.frames.insert(name, sprite_frame);
+        }
+
+        for tag in atlas.meta.frame_tags {
+            let separator = tag.name.find(TAG_SEPARATOR).ok_or_else(|| {
+                GameError::ResourceLoadError(format!(
+                    "Frame tag {} isn't named <set>{}<clip>",
+                    tag.name, TAG_SEPARATOR
+                ))
+            })?;
+
+            let tagged = match frames.get(tag.from..=tag.to) {
+                Some(tagged) if !tagged.is_empty() => tagged,
+                _ => {
+                    return Err(GameError::ResourceLoadError(format!(
+                        "Frame tag {} spans frames {} to {}, out of {} frames",
+                        tag.name,
+                        tag.from,
+                        tag.to,
+                        frames.len()
+                    )))
+                }
+            };
+
+            let pingpong = tag.direction == TagDirection::Pingpong || tag.direction == TagDirection::PingpongReverse;
+
+            let mode = if pingpong {
+                LoopMode::PingPong
+            } else if tag.repeat.as_ref().map_or(false, |repeat| repeat.trim() == "1") {
+                LoopMode::Once
+            } else {
+                LoopMode::Loop
+            };
+
+            let mut clip = AnimationClip {
+                frames: tagged.iter().map(|&(frame, _)| frame).collect(),
+                durations: tagged.iter().map(|&(_, duration)| duration).collect(),
+                mode,
+            };
+
+            if tag.direction == TagDirection::Reverse || tag.direction == TagDirection::PingpongReverse {
+                clip.frames.reverse();
+                clip.durations.reverse();
+            }
+
+            let (set, name) = (&tag.name[..separator], &tag.name[separator + 1..]);
+            self.clips.entry(set.to_string()).or_default().insert(name.to_string(), clip);
+        }
+
+        Ok(())
+    }
+}
+
+/// Every texture listed in `resources/textures.json`, each drawn through its own sprite batch.
+pub struct Textures {
+    batches: Vec<SpriteBatch>,
+    index: TextureIndex,
+}
+
+impl Textures {
+    pub fn load(ctx: &mut Context) -> GameResult<Self> {
+        let file = filesystem::open(ctx, TEXTURES_PATH)?;
+        let entries: Vec<TextureEntry> = serde_json::from_reader(file).map_err(|e| {
+            GameError::ResourceLoadError(format!("Failed to parse textures: {}", e))
+        })?;
+
+        if entries.is_empty() {
+            return Err(GameError::ResourceLoadError("No textures listed".to_string()));
+        }
+
+        let mut batches = Vec::new();
+        let mut index = TextureIndex::default();
+
+        for (position, entry) in entries.into_iter().enumerate() {
+            let handle = TextureHandle(position);
+            let image = Image::new(ctx, &entry.path)?;
+
+            if let Some(ref path) = entry.atlas {
+                let (width, height) = (f32::from(image.width()), f32::from(image.height()));
+                let file = filesystem::open(ctx, path)?;
+                let atlas: AtlasData = serde_json::from_reader(file).map_err(|e| {
+                    GameError::ResourceLoadError(format!("Failed to parse atlas of texture {}: {}", entry.name, e))
+                })?;
+
+                index.add_atlas(handle, atlas, width, height)?;
+            }
+
+            let mut batch = SpriteBatch::new(image);
+            batch.set_filter(FilterMode::Nearest);
+
+            batches.push(batch);
+        }
+
+        Ok(Self { batches, index })
+    }
+
+    pub fn index(&self) -> &TextureIndex {
+        &self.index
+    }
+
+    /// The batch drawing with the texture. Handles only come from the textures loaded here, so
+    /// every one of them has a batch.
+    pub fn batch_mut(&mut self, handle: TextureHandle) -> &mut SpriteBatch {
+        &mut self.batches[handle.0]
+    }
+
+    pub fn clear(&mut self) {
+        for batch in self.batches.iter_mut() {
+            batch.clear();
+        }
+    }
+}
@@ -9,18 +9,73 @@ use graphics::{DrawParam, Mesh};
 
 use crate::util::lerp::Lerp;
 
+use super::resources::LevelBounds;
+
+/// Seconds it takes the camera to close half the distance to where it's headed.
+const FOLLOW_HALF_LIFE: f32 = 0.035;
+const ZOOM_HALF_LIFE: f32 = 0.035;
+/// How far the target can move sideways from the focus point before the camera scrolls.
+const DEADZONE_HALF_WIDTH: f32 = 12.0;
+/// How far ahead of the target the camera looks in the direction it's going.
+const LOOK_AHEAD_DISTANCE: f32 = 32.0;
+/// Speed above which the target counts as going somewhere, rather than adjusting its footing.
+const LOOK_AHEAD_MIN_SPEED: f32 = 20.0;
+const LOOK_AHEAD_HALF_LIFE: f32 = 0.25;
+/// How far above the ground it last stood on the target can get before the camera follows it up.
+const VERTICAL_ESCAPE: f32 = 64.0;
+/// Shake intensity below which the shake stops.
+const SHAKE_CUTOFF: f32 = 0.1;
+
+/// Scripted camera moves. Queued moves run one after the other, after which the camera goes
+/// back to following its target.
+#[derive(Clone, Copy, Debug)]
+pub enum CameraCommand {
+    /// Moves to `to` over `ticks` ticks, easing in and out.
+    Pan { to: Point2<f32>, ticks: u32 },
+    /// Stays put for `ticks` ticks.
+    Hold { ticks: u32 },
+}
+
+/// A queued camera command, and whether the rest of the game waits while it runs.
+#[derive(Clone, Copy, Debug)]
+struct QueuedCommand {
+    command: CameraCommand,
+    pauses_play: bool,
+}
+
 pub struct Camera {
     pub position: Point2<f32>,
     pub zoom: f32,
+    /// Where the camera was the tick before, to draw in between ticks.
+    previous_position: Point2<f32>,
+    previous_zoom: f32,
     target_position: Point2<f32>,
     target_zoom: f32,
+    target_velocity_x: f32,
+    target_grounded: bool,
+    /// Point the camera centers on before looking ahead, held still while the target is in the deadzone.
+    focus: Point2<f32>,
+    look_ahead: f32,
+    bounds: Option<LevelBounds>,
+    /// Size of the screen in pixels.
+    viewport: Vector2<f32>,
+    commands: VecDeque<QueuedCommand>,
+    /// Where the camera was when the current command started.
+    command_start: Point2<f32>,
+    command_ticks: u32,
+    shake_intensity: f32,
+    shake_half_life: f32,
+    shake_offset: Vector2<f32>,
+    shake_ticks: u32,
 }
 
 impl Camera {
-    pub fn render(&self, ctx: &mut Context, batch: &SpriteBatch) -> GameResult<()> {
+    /// Draws the batch, `alpha` being how far the game is between the previous tick and the current one.
+    pub fn render(&self, ctx: &mut Context, batch: &SpriteBatch, alpha: f32) -> GameResult<()> {
+        let zoom = self.view_zoom(alpha);
         let param = graphics::DrawParam::new()
             .offset(Point2::new(0.5, 0.5))
-            .scale(Vector2::new(self.zoom, self.zoom));
+            .scale(Vector2::new(zoom, zoom));
 
         graphics::draw(ctx, batch, param)?;
 
@@ -31,7 +86,10 @@ impl Camera {
         &self,
         ctx: &mut Context,
         debug_renderables: &mut VecDeque<Mesh>,
+        alpha: f32,
     ) -> GameResult<()> {
+        let zoom = self.view_zoom(alpha);
+
         while !debug_renderables.is_empty() {
             let mesh = debug_renderables.pop_front().unwrap();
             graphics::draw(
@@ -39,7 +97,7 @@ impl Camera {
                 &mesh,
                 DrawParam::default()
                     .offset(Point2::new(0.5, 0.5))
-                    .scale(Vector2::new(self.zoom, self.zoom)),
+                    .scale(Vector2::new(zoom, zoom)),
             )?;
         }
 
@@ -50,17 +108,218 @@ impl Camera {
         self.target_position = position.clone();
     }
 
+    /// How the target is moving, which decides where the camera looks and when it scrolls up.
+    pub fn set_target_motion(&mut self, velocity_x: f32, grounded: bool) {
+        self.target_velocity_x = velocity_x;
+        self.target_grounded = grounded;
+    }
+
+    /// Keeps the view inside the level.
+    pub fn set_bounds(&mut self, bounds: LevelBounds) {
+        self.bounds = Some(bounds);
+    }
+
+    pub fn set_viewport(&mut self, width: f32, height: f32) {
+        self.viewport = Vector2::new(width, height);
+    }
+
+    /// Jumps straight to a zoom level.
     pub fn set_zoom(&mut self, zoom: f32) {
         self.zoom = zoom;
+        self.previous_zoom = zoom;
+        self.target_zoom = zoom;
+    }
+
+    /// Jumps straight to a position, without drifting over from wherever the camera was.
+    pub fn jump_to(&mut self, position: Point2<f32>) {
+        self.position = position;
+        self.previous_position = position;
+        self.focus = position;
+    }
+
+    /// Zooms smoothly to a zoom level.
+    pub fn zoom_to(&mut self, zoom: f32) {
+        self.target_zoom = zoom;
+    }
+
+    /// Shakes the view by up to `intensity` pixels, dying down by half every `half_life` seconds.
+    /// A weaker shake doesn't cut short a stronger one that's still going.
+    pub fn shake(&mut self, intensity: f32, half_life: f32) {
+        if intensity >= self.shake_intensity {
+            self.shake_intensity = intensity;
+            self.shake_half_life = half_life;
+        }
+    }
+
+    /// Queues a pan to `to` over `ticks` ticks, with the rest of the game paused meanwhile if
+    /// `pauses_play` is set.
+    pub fn pan_to(&mut self, to: Point2<f32>, ticks: u32, pauses_play: bool) {
+        self.queue(CameraCommand::Pan { to, ticks }, pauses_play);
+    }
+
+    /// Queues holding still for `ticks` ticks, with the rest of the game paused meanwhile if
+    /// `pauses_play` is set.
+    pub fn hold(&mut self, ticks: u32, pauses_play: bool) {
+        self.queue(CameraCommand::Hold { ticks }, pauses_play);
     }
 
-    pub fn update(&mut self) {
-        let ratio = 0.3;
+    fn queue(&mut self, command: CameraCommand, pauses_play: bool) {
+        self.commands.push_back(QueuedCommand { command, pauses_play });
+    }
+
+    /// Drops any queued commands, going back to following the target.
+    pub fn release(&mut self) {
+        self.commands.clear();
+        self.command_ticks = 0;
+    }
+
+    /// Whether the command the camera is running pauses the rest of the game.
+    pub fn pauses_play(&self) -> bool {
+        self.commands.front().map_or(false, |queued| queued.pauses_play)
+    }
 
-        self.position.x = self.position.x.lerp(self.target_position.x, ratio);
-        self.position.y = self.position.y.lerp(self.target_position.y, ratio);
+    /// Where the view is centered `alpha` of the way from the previous tick to the current one,
+    /// shake included.
+    pub fn view_position(&self, alpha: f32) -> Point2<f32> {
+        Point2::from(self.previous_position.coords.lerp_bounded(self.position.coords, alpha)) + self.shake_offset
+    }
+
+    pub fn view_zoom(&self, alpha: f32) -> f32 {
+        self.previous_zoom.lerp_bounded(self.zoom, alpha)
+    }
+
+    /// Moves the camera on by `delta` seconds.
+    pub fn update(&mut self, delta: f32) {
+        self.previous_position = self.position;
+        self.previous_zoom = self.zoom;
+
+        if !self.run_commands() {
+            self.follow_target(delta);
+        }
+
+        self.zoom = self.zoom.lerp(self.target_zoom, smoothing(delta, ZOOM_HALF_LIFE));
+
+        self.update_shake(delta);
+    }
+
+    /// Runs a tick of the current command, returning whether there was one.
+    fn run_commands(&mut self) -> bool {
+        let command = match self.commands.front() {
+            Some(queued) => queued.command,
+            None => return false,
+        };
+
+        if self.command_ticks == 0 {
+            self.command_start = self.position;
+        }
+
+        self.command_ticks += 1;
+
+        let done = match command {
+            CameraCommand::Pan { to, ticks } => {
+                let t = self.command_ticks as f32 / ticks.max(1) as f32;
+                let eased = t * t * (3.0 - 2.0 * t);
+                let to = self.clamp_to_bounds(to);
+                self.position = Point2::from(self.command_start.coords.lerp_bounded(to.coords, eased));
+
+                self.command_ticks >= ticks
+            }
+            CameraCommand::Hold { ticks } => {
+                self.position = self.clamp_to_bounds(self.position);
+
+                self.command_ticks >= ticks
+            }
+        };
+
+        if done {
+            self.commands.pop_front();
+            self.command_ticks = 0;
+        }
+
+        true
+    }
+
+    fn follow_target(&mut self, delta: f32) {
+        let offset_x = self.target_position.x - self.focus.x;
+
+        if offset_x.abs() > DEADZONE_HALF_WIDTH {
+            self.focus.x = self.target_position.x - offset_x.signum() * DEADZONE_HALF_WIDTH;
+        }
+
+        if self.target_velocity_x.abs() > LOOK_AHEAD_MIN_SPEED {
+            let look_ahead = self.target_velocity_x.signum() * LOOK_AHEAD_DISTANCE;
+            self.look_ahead = self.look_ahead.lerp(look_ahead, smoothing(delta, LOOK_AHEAD_HALF_LIFE));
+        }
+
+        // Jumps don't scroll the camera up, it waits for the target to land on higher ground.
+        // Falling below the last ground or climbing far out of view still gets followed.
+        let target_y = self.target_position.y;
+
+        if self.target_grounded || target_y < self.focus.y {
+            self.focus.y = target_y;
+        } else if target_y > self.focus.y + VERTICAL_ESCAPE {
+            self.focus.y = target_y - VERTICAL_ESCAPE;
+        }
+
+        let destination = self.clamp_to_bounds(Point2::new(self.focus.x + self.look_ahead, self.focus.y));
+
+        let ratio = smoothing(delta, FOLLOW_HALF_LIFE);
+
+        self.position.x = self.position.x.lerp(destination.x, ratio);
+        self.position.y = self.position.y.lerp(destination.y, ratio);
+    }
+
+    fn update_shake(&mut self, delta: f32) {
+        if self.shake_intensity < SHAKE_CUTOFF {
+            self.shake_intensity = 0.0;
+            self.shake_offset = Vector2::new(0.0, 0.0);
+            return;
+        }
+
+        self.shake_ticks = self.shake_ticks.wrapping_add(1);
+
+        // Two unrelated frequencies per axis, so the shake doesn't trace a visible pattern.
+        let t = self.shake_ticks as f32;
+        let x = (t * 1.7).sin() + (t * 3.1).sin() * 0.5;
+        let y = (t * 2.3).sin() + (t * 4.3).sin() * 0.5;
+
+        self.shake_offset = Vector2::new(x, y) * self.shake_intensity / 1.5;
+        self.shake_intensity *= 1.0 - smoothing(delta, self.shake_half_life);
+    }
+
+    /// Moves a camera position so the view doesn't show anything past the level edges.
+    /// A level smaller than the view is centered instead.
+    fn clamp_to_bounds(&self, position: Point2<f32>) -> Point2<f32> {
+        let bounds = match self.bounds {
+            Some(bounds) => bounds,
+            None => return position,
+        };
+
+        let half_width = self.viewport.x / (2.0 * self.zoom);
+        let half_height = self.viewport.y / (2.0 * self.zoom);
+
+        Point2::new(
+            clamp_axis(position.x, bounds.left + half_width, bounds.right - half_width),
+            clamp_axis(position.y, bounds.bottom + half_height, bounds.top - half_height),
+        )
+    }
+}
+
+/// Fraction of the way to its destination something with the given half-life gets in `delta`
+/// seconds, so smoothing looks the same whatever the tick rate.
+fn smoothing(delta: f32, half_life: f32) -> f32 {
+    if half_life <= 0.0 {
+        1.0
+    } else {
+        1.0 - 0.5f32.powf(delta / half_life)
+    }
+}
 
-        self.zoom = self.zoom.lerp(self.target_zoom, ratio);
+fn clamp_axis(value: f32, min: f32, max: f32) -> f32 {
+    if min > max {
+        (min + max) / 2.0
+    } else {
+        value.max(min).min(max)
     }
 }
 
@@ -69,8 +328,23 @@ impl Default for Camera {
         Camera {
             position: Point2::new(0.0, 0.0),
             zoom: 4.0,
+            previous_position: Point2::new(0.0, 0.0),
+            previous_zoom: 4.0,
             target_zoom: 4.0,
             target_position: Point2::new(0.0, 0.0),
+            target_velocity_x: 0.0,
+            target_grounded: true,
+            focus: Point2::new(0.0, 0.0),
+            look_ahead: 0.0,
+            bounds: None,
+            viewport: Vector2::new(0.0, 0.0),
+            commands: VecDeque::new(),
+            command_start: Point2::new(0.0, 0.0),
+            command_ticks: 0,
+            shake_intensity: 0.0,
+            shake_half_life: 0.0,
+            shake_offset: Vector2::new(0.0, 0.0),
+            shake_ticks: 0,
         }
     }
 }
@@ -2,36 +2,88 @@ use ggez::{
     Context,
     event::EventHandler,
     GameResult,
-    graphics, nalgebra::{Point2, Vector2}, timer,
+    graphics, nalgebra::Point2, timer,
 };
-use graphics::{FilterMode, Rect};
-use specs::{Builder, DispatcherBuilder, World, WorldExt};
+use specs::{DispatcherBuilder, World, WorldExt};
 
 use crate::{
     components::{Body, CameraTarget, Player, Sprite, Transform},
     engine::scene_manager::SceneManager,
-    entities::{Brick, Mario},
+    entities::SPRITE_FRAMES,
     systems::{CameraSystem, PhysicsSystem, PlayerControlSystem},
 };
-use crate::components::{Animation, FeetSensor, Jumper, BasicAI};
-use crate::systems::{AnimationSystem, EnemySystem};
+use crate::components::{Animation, FeetSensor, Jumper, BasicAI, Killed, DeathSequence, Checkpoint, CharacterController};
+use crate::components::{DropThrough, Goal, GravityScale, LevelComplete, MovingPlatform, Pipe, PipeTransit, PowerState};
+use crate::components::{Breakable, Carrier, Carryable, Crouch, Dust, InWater, PreviousTransform, RenderLayer, SpecialMove, Spiky, SwimAI};
+use crate::systems::{AnimationSystem, CheckpointSystem, FootstepSystem, DeathSystem, EnemySystem, GoalSystem, LevelTimerSystem, PipeSystem};
+use crate::systems::{CharacterControllerSystem, MovingPlatformSystem, SpecialMoveSystem, WaterSystem, CarrySystem};
 
-use super::{physics::PhysicsWorld, scene_manager::Scene};
-use crate::entities::KoopaTroopa;
+use super::{
+    animation::AnimationGraphs,
+    assets::{TextureIndex, Textures},
+    level::{AreaData, LevelData},
+    physics::PhysicsWorld,
+    resources::{GameSession, LevelInfo, LevelTimer, SceneTransition},
+    scene_manager::Scene,
+    tuning::{Tuning, TuningFile},
+};
 
 pub const TARGET_FPS: u32 = 60;
+pub const WINDOW_TITLE: &str = "Super Mario";
+pub const FIRST_LEVEL: &str = "1-1";
+
+/// How far the game is from its last tick to the next one, for drawing in between ticks.
+pub fn interpolation_alpha(ctx: &mut Context) -> f32 {
+    let remaining = timer::remaining_update_time(ctx).as_secs_f32();
+
+    (remaining * TARGET_FPS as f32).min(1.0)
+}
 
 pub struct SuperMario<'a, 'b> {
     pub is_running: bool,
     scene_manager: SceneManager<'a, 'b>,
-    batch: graphics::spritebatch::SpriteBatch,
+    textures: Textures,
+    animations: AnimationGraphs,
+    level: LevelData,
+    tuning: TuningFile,
 }
 
 impl<'a, 'b> SuperMario<'a, 'b> {
     pub fn new(ctx: &mut Context) -> GameResult<Self> {
-        let mut physics_world = PhysicsWorld::default();
+        let tuning = TuningFile::load(ctx)?;
+        let textures = Textures::load(ctx)?;
+        textures.index().check_frames(&SPRITE_FRAMES)?;
+        let animations = AnimationGraphs::load(ctx, textures.index())?;
+        let level = LevelData::load(ctx, FIRST_LEVEL, textures.index())?;
+        let mut first_scene =
+            Self::build_scene(&level, level.area(None)?, None, tuning.tuning, textures.index(), &animations);
+        first_scene.play_intro();
+
+        let game = Self {
+            is_running: false,
+            scene_manager: SceneManager::new(first_scene),
+            textures,
+            animations,
+            level,
+            tuning,
+        };
+
+        Ok(game)
+    }
+
+    fn build_scene(
+        level: &LevelData,
+        area: &AreaData,
+        player_start: Option<Point2<f32>>,
+        tuning: Tuning,
+        textures: &TextureIndex,
+        animations: &AnimationGraphs,
+    ) -> Scene<'a, 'b> {
+        let mut physics_world = PhysicsWorld::new(&tuning.physics);
         let mut world = World::new();
         world.register::<Transform>();
+        world.register::<PreviousTransform>();
+        world.register::<RenderLayer>();
         world.register::<Sprite>();
         world.register::<Body>();
         world.register::<CameraTarget>();
@@ -40,37 +92,133 @@ impl<'a, 'b> SuperMario<'a, 'b> {
         world.register::<FeetSensor>();
         world.register::<Jumper>();
         world.register::<BasicAI>();
+        world.register::<Killed>();
+        world.register::<DeathSequence>();
+        world.register::<Checkpoint>();
+        world.register::<Goal>();
+        world.register::<LevelComplete>();
+        world.register::<PowerState>();
+        world.register::<Pipe>();
+        world.register::<PipeTransit>();
+        world.register::<MovingPlatform>();
+        world.register::<DropThrough>();
+        world.register::<CharacterController>();
+        world.register::<GravityScale>();
+        world.register::<SpecialMove>();
+        world.register::<Breakable>();
+        world.register::<Spiky>();
+        world.register::<Crouch>();
+        world.register::<InWater>();
+        world.register::<SwimAI>();
+        world.register::<Carryable>();
+        world.register::<Carrier>();
+        world.register::<Dust>();
 
+        world.insert(tuning);
+        world.insert(textures.clone());
+        world.insert(animations.clone());
+        area.spawn(&mut world, &mut physics_world, player_start);
 
-        Mario::add(&mut world, Point2::new(0.0, 0.0), &mut physics_world);
-        KoopaTroopa::add(&mut world, Point2::new(32.0, 0.0) , &mut physics_world);
-
-        for i in 0..1 {
-            Brick::add(&mut world, Point2::new(i as f32 * 7.0 * 16.0, (i + 1) as f32 * -32.0), nphysics2d::nalgebra::base::Vector2::new(30, 2), &mut physics_world);
-        }
-      
+        world.insert(LevelTimer::new(level.time_limit));
+        world.insert(area.bounds);
+        world.insert(LevelInfo {
+            level: level.name.clone(),
+            area: area.name.clone(),
+        });
+        world.insert(GameSession::default());
 
         let dispatcher = DispatcherBuilder::new()
-            .with(PlayerControlSystem, "PlayerControlSystem", &[])
-            .with(PhysicsSystem, "PhysicsSystem", &["PlayerControlSystem"])
-            .with(AnimationSystem, "AnimationSystem", &[])
-            .with(CameraSystem, "CameraSystem", &[])
+            .with(LevelTimerSystem, "LevelTimerSystem", &[])
+            .with(PlayerControlSystem, "PlayerControlSystem", &["LevelTimerSystem"])
+            .with(MovingPlatformSystem, "MovingPlatformSystem", &[])
+            .with(
+                CharacterControllerSystem,
+                "CharacterControllerSystem",
+                &["PlayerControlSystem", "MovingPlatformSystem"],
+            )
+            .with(CarrySystem, "CarrySystem", &["PlayerControlSystem"])
+            .with(PhysicsSystem, "PhysicsSystem", &["CharacterControllerSystem", "CarrySystem"])
+            .with(AnimationSystem, "AnimationSystem", &["PhysicsSystem", "PlayerControlSystem"])
+            .with(FootstepSystem, "FootstepSystem", &["AnimationSystem"])
+            .with(CameraSystem, "CameraSystem", &["PhysicsSystem", "SpecialMoveSystem", "GoalSystem"])
             .with(EnemySystem, "EnemySystem", &[])
+            .with(CheckpointSystem, "CheckpointSystem", &["PhysicsSystem"])
+            .with(DeathSystem, "DeathSystem", &["PhysicsSystem", "LevelTimerSystem"])
+            .with(GoalSystem, "GoalSystem", &["PhysicsSystem"])
+            .with(PipeSystem, "PipeSystem", &["PhysicsSystem"])
+            .with(SpecialMoveSystem, "SpecialMoveSystem", &["PhysicsSystem"])
+            .with(WaterSystem, "WaterSystem", &["PhysicsSystem"])
             .build();
-        let first_scene = Scene::new(world, dispatcher, physics_world);
 
-        let image = graphics::Image::new(ctx, "/textures.png").unwrap();
+        Scene::new(world, dispatcher, physics_world)
+    }
 
-        let mut batch = graphics::spritebatch::SpriteBatch::new(image);
-        batch.set_filter(FilterMode::Nearest);
+    fn handle_transition(&mut self, ctx: &mut Context, transition: SceneTransition) -> GameResult<()> {
+        let mut handoff = self.scene_manager.current_scene().handoff();
+        let mut area_name = None;
+        let mut player_start = None;
+        // Only a level started from the beginning gets its intro, not a retry or a warp.
+        let mut intro = false;
 
-        let game = Self {
-            is_running: false,
-            scene_manager: SceneManager::new(first_scene),
-            batch,
-        };
+        match transition {
+            SceneTransition::Restart => {
+                handoff.power = PowerState::default();
+                handoff.timer = None;
 
-        Ok(game)
+                if handoff.session.lives == 0 {
+                    handoff.session = GameSession::default();
+                    self.level = LevelData::load(ctx, FIRST_LEVEL, self.textures.index())?;
+                    intro = true;
+                }
+
+                if let Some(ref checkpoint) = handoff.session.checkpoint {
+                    area_name = Some(checkpoint.area.clone());
+                    player_start = Some(checkpoint.position);
+                }
+            }
+            SceneTransition::NextLevel => {
+                let next_level = match self.level.next_level {
+                    Some(ref name) => name.clone(),
+                    None => FIRST_LEVEL.to_string(),
+                };
+
+                self.level = LevelData::load(ctx, &next_level, self.textures.index())?;
+                handoff.session.checkpoint = None;
+                handoff.timer = None;
+                intro = true;
+            }
+            SceneTransition::Warp(destination) => {
+                if let Some(ref level) = destination.level {
+                    if *level != self.level.name {
+                        self.level = LevelData::load(ctx, level, self.textures.index())?;
+                        handoff.session.checkpoint = None;
+                    }
+                }
+
+                let area = self.level.area(Some(destination.area.as_str()))?;
+                player_start = Some(area.pipe_exit(&destination.pipe)?);
+                area_name = Some(destination.area);
+            }
+        }
+
+        let area = self.level.area(area_name.as_deref())?;
+        let mut scene = Self::build_scene(
+            &self.level,
+            area,
+            player_start,
+            self.tuning.tuning,
+            self.textures.index(),
+            &self.animations,
+        );
+        scene.receive(handoff);
+
+        if intro {
+            scene.play_intro();
+        }
+
+        self.scene_manager.replace_scene(scene);
+
+        Ok(())
     }
 }
 
@@ -78,6 +226,20 @@ impl<'a, 'b> EventHandler for SuperMario<'a, 'b> {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
         while timer::check_update_time(ctx, TARGET_FPS) {
             self.scene_manager.update(ctx)?;
+
+            if let Some(transition) = self.scene_manager.current_scene().take_transition() {
+                self.handle_transition(ctx, transition)?;
+            }
+
+            match self.tuning.poll(ctx) {
+                Ok(Some(tuning)) => {
+                    self.scene_manager.current_scene().apply_tuning(tuning);
+                    graphics::set_window_title(ctx, WINDOW_TITLE);
+                }
+                Ok(None) => {}
+                // A half-done edit shouldn't stop the game, so it's shown until the file is fixed.
+                Err(e) => graphics::set_window_title(ctx, &format!("{} - {}", WINDOW_TITLE, e)),
+            }
         }
 
         Ok(())
@@ -86,9 +248,9 @@ impl<'a, 'b> EventHandler for SuperMario<'a, 'b> {
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         graphics::clear(ctx, graphics::Color::new(0.2, 0.3, 0.5, 1.0));
 
-        self.scene_manager.draw(ctx, &mut self.batch)?;
+        self.scene_manager.draw(ctx, &mut self.textures)?;
 
-        self.batch.clear();
+        self.textures.clear();
         graphics::present(ctx)?;
 
         Ok(())
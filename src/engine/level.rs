@@ -0,0 +1,260 @@
+use ggez::{Context, filesystem, GameError, GameResult, nalgebra::Point2};
+use nphysics2d::nalgebra::base::Vector2;
+use serde::Deserialize;
+use specs::World;
+
+use crate::components::{PipeDirection, PlatformPath, RenderLayer, SlopeAngle, SlopeDirection, WarpDestination};
+use crate::entities::{Brick, CheckpointGate, Flagpole, KoopaTroopa, Mario, Platform, Slope, Spiny, TurnBlock, WarpPipe, CheepCheep, Scenery, Shell, ThrowBlock, Vine, WaterZone};
+
+use super::{assets::TextureIndex, physics::PhysicsWorld, resources::LevelBounds};
+
+/// Distance between a pipe mouth and the point a player comes out at.
+const PIPE_EXIT_OFFSET: f32 = 10.0;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct LevelData {
+    pub name: String,
+    /// Time limit of the level in seconds.
+    pub time_limit: u32,
+    /// Level loaded once the goal is reached, or the first level when missing.
+    #[serde(default)]
+    pub next_level: Option<String>,
+    /// Areas of the level, the level starts in the first one.
+    pub areas: Vec<AreaData>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct AreaData {
+    pub name: String,
+    pub bounds: LevelBounds,
+    pub entities: Vec<EntityData>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum EntityData {
+    Mario { x: f32, y: f32 },
+    KoopaTroopa { x: f32, y: f32 },
+    Spiny { x: f32, y: f32 },
+    CheepCheep { x: f32, y: f32 },
+    Shell { x: f32, y: f32 },
+    ThrowBlock { x: f32, y: f32 },
+    Brick {
+        x: f32,
+        y: f32,
+        width: u32,
+        height: u32,
+        #[serde(default)]
+        one_way: bool,
+    },
+    TurnBlock { x: f32, y: f32 },
+    Checkpoint { x: f32, y: f32 },
+    Goal { x: f32, y: f32, height: f32, castle_x: f32 },
+    Pipe {
+        x: f32,
+        y: f32,
+        width: u32,
+        height: u32,
+        direction: PipeDirection,
+        #[serde(default)]
+        id: Option<String>,
+        #[serde(default)]
+        destination: Option<WarpDestination>,
+    },
+    Platform { x: f32, y: f32, width: u32, path: PlatformPath },
+    Slope { x: f32, y: f32, run: u32, angle: SlopeAngle, direction: SlopeDirection },
+    Water { x: f32, y: f32, width: u32, height: u32 },
+    /// Vines and ladders alike, `height` in tiles.
+    Vine { x: f32, y: f32, height: u32 },
+    /// Tiles that are only drawn, in front of or behind the level depending on `layer`.
+    Scenery {
+        x: f32,
+        y: f32,
+        width: u32,
+        height: u32,
+        /// Name of the atlas frame to tile.
+        frame: String,
+        #[serde(default)]
+        layer: RenderLayer,
+    },
+}
+
+impl LevelData {
+    pub fn load(ctx: &mut Context, name: &str, textures: &TextureIndex) -> GameResult<Self> {
+        let file = filesystem::open(ctx, format!("/levels/{}.json", name))?;
+
+        let level: Self = serde_json::from_reader(file).map_err(|e| {
+            GameError::ResourceLoadError(format!("Failed to parse level {}: {}", name, e))
+        })?;
+
+        level.check_warps()?;
+        level.check_frames(textures)?;
+        level.check_slopes()?;
+
+        Ok(level)
+    }
+
+    /// Checks that every pipe warping within the level leads to an area and pipe that exist.
+    /// Pipes into other levels are checked once they're taken, as that needs the other level loaded.
+    fn check_warps(&self) -> GameResult<()> {
+        for area in self.areas.iter() {
+            for entity in area.entities.iter() {
+                if let EntityData::Pipe { destination: Some(ref destination), .. } = *entity {
+                    let same_level = destination.level.as_ref().map_or(true, |level| *level == self.name);
+
+                    if same_level {
+                        self.area(Some(destination.area.as_str()))?.pipe_exit(&destination.pipe)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that every sprite frame the level names itself is in the atlas.
+    fn check_frames(&self, textures: &TextureIndex) -> GameResult<()> {
+        for area in self.areas.iter() {
+            for entity in area.entities.iter() {
+                if let EntityData::Scenery { ref frame, .. } = *entity {
+                    if textures.frame(frame).is_none() {
+                        return Err(GameError::ResourceLoadError(format!(
+                            "Area {} uses missing sprite frame {}",
+                            area.name, frame
+                        )));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that every slope spans at least a tile, so it has a shape to collide with.
+    fn check_slopes(&self) -> GameResult<()> {
+        for area in self.areas.iter() {
+            for entity in area.entities.iter() {
+                if let EntityData::Slope { x, y, run: 0, .. } = *entity {
+                    return Err(GameError::ResourceLoadError(format!(
+                        "Area {} has a slope at ({}, {}) that spans no tiles",
+                        area.name, x, y
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Looks up an area by name, or the starting area when no name is given.
+    pub fn area(&self, name: Option<&str>) -> GameResult<&AreaData> {
+        let area = match name {
+            Some(name) => self.areas.iter().find(|area| area.name == name),
+            None => self.areas.first(),
+        };
+
+        area.ok_or_else(|| {
+            GameError::ResourceLoadError(format!("Level {} has no area {:?}", self.name, name))
+        })
+    }
+}
+
+impl AreaData {
+    /// Where a player coming out of the pipe with the given id appears.
+    pub fn pipe_exit(&self, id: &str) -> GameResult<Point2<f32>> {
+        let exit = self.entities.iter().find_map(|entity| match *entity {
+            EntityData::Pipe { x, y, width, height, direction, id: Some(ref pipe_id), .. } if pipe_id == id => {
+                let opening = WarpPipe::opening(Point2::new(x, y), Vector2::new(width, height), direction);
+
+                let exit = match direction {
+                    PipeDirection::Down => Point2::new(opening.x, opening.y + PIPE_EXIT_OFFSET),
+                    PipeDirection::Up => Point2::new(opening.x, opening.y - PIPE_EXIT_OFFSET),
+                    PipeDirection::Right => Point2::new(opening.x - PIPE_EXIT_OFFSET, opening.y),
+                    PipeDirection::Left => Point2::new(opening.x + PIPE_EXIT_OFFSET, opening.y),
+                };
+
+                Some(exit)
+            }
+            _ => None,
+        });
+
+        exit.ok_or_else(|| GameError::ResourceLoadError(format!("Area {} has no pipe {}", self.name, id)))
+    }
+
+    /// Adds the area entities to the world, placing the player at `player_start` when given.
+    pub fn spawn(
+        &self,
+        world: &mut World,
+        physics_world: &mut PhysicsWorld,
+        player_start: Option<Point2<f32>>,
+    ) {
+        let mut player_spawned = false;
+
+        for entity in self.entities.iter() {
+            match *entity {
+                EntityData::Mario { x, y } => {
+                    let position = player_start.unwrap_or_else(|| Point2::new(x, y));
+                    Mario::add(world, position, physics_world);
+                    player_spawned = true;
+                }
+                EntityData::KoopaTroopa { x, y } => {
+                    KoopaTroopa::add(world, Point2::new(x, y), physics_world);
+                }
+                EntityData::Brick { x, y, width, height, one_way } => {
+                    Brick::add(world, Point2::new(x, y), Vector2::new(width, height), one_way, physics_world);
+                }
+                EntityData::Spiny { x, y } => {
+                    Spiny::add(world, Point2::new(x, y), physics_world);
+                }
+                EntityData::CheepCheep { x, y } => {
+                    CheepCheep::add(world, Point2::new(x, y), physics_world);
+                }
+                EntityData::Shell { x, y } => {
+                    Shell::add(world, Point2::new(x, y), physics_world);
+                }
+                EntityData::ThrowBlock { x, y } => {
+                    ThrowBlock::add(world, Point2::new(x, y), physics_world);
+                }
+                EntityData::TurnBlock { x, y } => {
+                    TurnBlock::add(world, Point2::new(x, y), physics_world);
+                }
+                EntityData::Checkpoint { x, y } => {
+                    CheckpointGate::add(world, Point2::new(x, y), physics_world);
+                }
+                EntityData::Goal { x, y, height, castle_x } => {
+                    Flagpole::add(world, Point2::new(x, y), height, castle_x, physics_world);
+                }
+                EntityData::Pipe { x, y, width, height, direction, ref destination, .. } => {
+                    WarpPipe::add(
+                        world,
+                        Point2::new(x, y),
+                        Vector2::new(width, height),
+                        direction,
+                        destination.clone(),
+                        physics_world,
+                    );
+                }
+                EntityData::Platform { x, y, width, path } => {
+                    Platform::add(world, Point2::new(x, y), width, path, physics_world);
+                }
+                EntityData::Slope { x, y, run, angle, direction } => {
+                    Slope::add(world, Point2::new(x, y), run, angle, direction, physics_world);
+                }
+                EntityData::Water { x, y, width, height } => {
+                    WaterZone::add(world, Point2::new(x, y), Vector2::new(width, height), physics_world);
+                }
+                EntityData::Vine { x, y, height } => {
+                    Vine::add(world, Point2::new(x, y), height, physics_world);
+                }
+                EntityData::Scenery { x, y, width, height, ref frame, layer } => {
+                    Scenery::add(world, Point2::new(x, y), frame, Vector2::new(width, height), layer);
+                }
+            }
+        }
+
+        // Areas only reached through pipes have no start position of their own.
+        if let (false, Some(position)) = (player_spawned, player_start) {
+            Mario::add(world, position, physics_world);
+        }
+    }
+}
@@ -2,4 +2,8 @@ pub mod game;
 pub mod resources;
 pub mod scene_manager;
 pub mod camera;
-pub mod physics;
\ No newline at end of file
+pub mod physics;
+pub mod level;
+pub mod tuning;
+pub mod assets;
+pub mod animation;
\ No newline at end of file
@@ -1,17 +1,107 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
 use nphysics2d::{
     force_generator::DefaultForceGeneratorSet,
     joint::DefaultJointConstraintSet,
-    nalgebra::Vector2,
+    material::{BasicMaterial, MaterialHandle},
+    nalgebra::{Isometry2, Vector2},
+    ncollide2d::pipeline::{BroadPhasePairFilter, CollisionGroups},
+    ncollide2d::query::{self, Proximity},
     ncollide2d::shape::{Ball, ShapeHandle},
     object::{DefaultBodySet, DefaultColliderSet, RigidBody},
     world::{DefaultGeometricalWorld, DefaultMechanicalWorld},
 };
 use nphysics2d::object::{
-    BodyPartHandle, Collider, ColliderDesc, DefaultBodyHandle, DefaultColliderHandle, RigidBodyDesc,
+    Body as _, BodyPartHandle, BodyStatus, Collider, ColliderDesc, DefaultBodyHandle, DefaultColliderHandle,
+    RigidBodyDesc,
 };
 
 use crate::components::Body;
 
+use super::tuning::PhysicsProfile;
+
+/// Collision group ordinary colliders are in, along with every other group, as that's the default.
+/// One-way colliders leave it.
+const SOLID_GROUP: usize = 0;
+/// Collision group of colliders that can only be landed on from above.
+pub const ONE_WAY_GROUP: usize = 1;
+/// How far below the top of a one-way collider a body can be and still land on it.
+const ONE_WAY_TOLERANCE: f32 = 4.0;
+/// How far apart two colliders can be and still count as touching.
+const TOUCHING_DISTANCE: f32 = 0.5;
+
+/// Gap a character keeps from whatever it runs into, so it doesn't start its next move inside it.
+const CHARACTER_SKIN: f32 = 0.05;
+/// How many times a character move can slide along a surface before it gives up.
+const CHARACTER_MAX_SLIDES: usize = 4;
+/// Surfaces with a normal pointing up at least this much can be stood on, about 50 degrees.
+const WALKABLE_NORMAL_Y: f32 = 0.64;
+
+/// Outcome of moving a character through the level.
+#[derive(Clone, Copy, Debug)]
+pub struct CharacterMove {
+    /// How far the character actually got.
+    pub translation: Vector2<f32>,
+    /// Body and surface normal of the ground it ended up on.
+    pub ground: Option<(DefaultBodyHandle, Vector2<f32>)>,
+    /// Surface normal of a wall it ran into.
+    pub wall: Option<Vector2<f32>>,
+    pub ceiling: bool,
+}
+
+/// A collider going in or out of a sensor volume during the last step.
+#[derive(Clone, Copy, Debug)]
+pub enum VolumeEvent {
+    Entered {
+        volume: DefaultColliderHandle,
+        collider: DefaultColliderHandle,
+    },
+    Left {
+        volume: DefaultColliderHandle,
+        collider: DefaultColliderHandle,
+    },
+}
+
+impl CharacterMove {
+    fn unobstructed(translation: Vector2<f32>) -> Self {
+        Self {
+            translation,
+            ground: None,
+            wall: None,
+            ceiling: false,
+        }
+    }
+}
+
+/// Pairs of a collider and a one-way collider it currently passes through.
+type OneWayPasses = Arc<RwLock<HashSet<(DefaultColliderHandle, DefaultColliderHandle)>>>;
+
+/// Keeps the broad phase from pairing colliders with the one-way colliders they pass through.
+struct OneWayFilter(OneWayPasses);
+
+impl BroadPhasePairFilter<f32, Collider<f32, DefaultBodyHandle>, DefaultColliderHandle> for OneWayFilter {
+    fn is_pair_valid(
+        &self,
+        _: &Collider<f32, DefaultBodyHandle>,
+        _: &Collider<f32, DefaultBodyHandle>,
+        h1: DefaultColliderHandle,
+        h2: DefaultColliderHandle,
+    ) -> bool {
+        self.0.read().map_or(true, |passes| !passes.contains(&(h1, h2)) && !passes.contains(&(h2, h1)))
+    }
+}
+
+/// A body held by another, following it around as a kinematic body.
+#[derive(Clone, Copy, Debug)]
+struct Carried {
+    carrier: DefaultBodyHandle,
+    carrier_collider: DefaultColliderHandle,
+    collider: DefaultColliderHandle,
+    /// Where the carried collider sits relative to the collider of its carrier.
+    offset: Vector2<f32>,
+}
+
 pub struct PhysicsWorld {
     mechanical_world: DefaultMechanicalWorld<f32>,
     pub geometrical_world: DefaultGeometricalWorld<f32>,
@@ -19,10 +109,17 @@ pub struct PhysicsWorld {
     pub(crate) bodies: DefaultBodySet<f32>,
     joint_constraints: DefaultJointConstraintSet<f32>,
     force_generators: DefaultForceGeneratorSet<f32>,
+    /// Sensor volumes along with the colliders currently inside each of them.
+    sensor_volumes: HashMap<DefaultColliderHandle, HashSet<DefaultColliderHandle>>,
+    volume_events: Vec<VolumeEvent>,
+    carried: HashMap<DefaultBodyHandle, Carried>,
+    one_way_passes: OneWayPasses,
 }
 
 impl PhysicsWorld {
     pub fn step(&mut self) {
+        self.move_carried();
+
         self.mechanical_world.step(
             &mut self.geometrical_world,
             &mut self.bodies,
@@ -30,6 +127,41 @@ impl PhysicsWorld {
             &mut self.joint_constraints,
             &mut self.force_generators,
         );
+
+        self.update_sensor_volumes();
+    }
+
+    pub fn new(profile: &PhysicsProfile) -> Self {
+        let mechanical_world = DefaultMechanicalWorld::new(Vector2::new(0.0, profile.gravity));
+        let mut geometrical_world = DefaultGeometricalWorld::new();
+        let one_way_passes = OneWayPasses::default();
+        geometrical_world.set_broad_phase_pair_filter(OneWayFilter(one_way_passes.clone()));
+
+        let bodies = DefaultBodySet::new();
+        let colliders = DefaultColliderSet::new();
+        let joint_constraints = DefaultJointConstraintSet::new();
+        let force_generators = DefaultForceGeneratorSet::new();
+
+        Self {
+            mechanical_world,
+            geometrical_world,
+            bodies,
+            colliders,
+            joint_constraints,
+            force_generators,
+            sensor_volumes: HashMap::new(),
+            volume_events: Vec::new(),
+            carried: HashMap::new(),
+            one_way_passes,
+        }
+    }
+
+    pub fn gravity(&self) -> Vector2<f32> {
+        self.mechanical_world.gravity
+    }
+
+    pub fn apply_profile(&mut self, profile: &PhysicsProfile) {
+        self.mechanical_world.gravity = Vector2::new(0.0, profile.gravity);
     }
 
     pub fn insert_body(
@@ -56,25 +188,452 @@ impl PhysicsWorld {
         let collider_handle = self.colliders.insert(collider);
         collider_handle
     }
-}
 
-impl Default for PhysicsWorld {
-    fn default() -> Self {
-        let mechanical_world = DefaultMechanicalWorld::new(Vector2::new(0.0, -450.0));
-        let geometrical_world = DefaultGeometricalWorld::new();
+    /// Inserts a sensor that keeps track of the colliders inside of it, see `volume_events`.
+    /// Overlaps are found geometrically, so kinematic characters are picked up as well.
+    pub fn insert_sensor_volume(
+        &mut self,
+        rigid_body: RigidBody<f32>,
+        collider_desc: ColliderDesc<f32>,
+    ) -> Body {
+        let body = self.insert_body(rigid_body, collider_desc.sensor(true));
+        self.sensor_volumes.insert(body.collider_handle, HashSet::new());
 
-        let bodies = DefaultBodySet::new();
-        let colliders = DefaultColliderSet::new();
-        let joint_constraints = DefaultJointConstraintSet::new();
-        let force_generators = DefaultForceGeneratorSet::new();
+        body
+    }
 
-        Self {
-            mechanical_world,
-            geometrical_world,
-            bodies,
-            colliders,
-            joint_constraints,
-            force_generators,
+    /// Colliders that went in or out of sensor volumes during the last step.
+    pub fn volume_events(&self) -> &[VolumeEvent] {
+        &self.volume_events
+    }
+
+    /// Sensor volumes the collider is currently inside of.
+    pub fn volumes_containing(
+        &self,
+        collider_handle: DefaultColliderHandle,
+    ) -> impl Iterator<Item = DefaultColliderHandle> + '_ {
+        self.sensor_volumes
+            .iter()
+            .filter(move |(_, inside)| inside.contains(&collider_handle))
+            .map(|(volume, _)| *volume)
+    }
+
+    /// The `&str` tag a collider was built with through `ColliderDesc::user_data`.
+    pub fn collider_tag(&self, collider_handle: DefaultColliderHandle) -> Option<&'static str> {
+        self.colliders
+            .get(collider_handle)
+            .and_then(|collider| collider.user_data())
+            .and_then(|user_data| user_data.downcast_ref::<&'static str>())
+            .copied()
+    }
+
+    fn update_sensor_volumes(&mut self) {
+        let colliders = &self.colliders;
+        let geometrical_world = &self.geometrical_world;
+        let events = &mut self.volume_events;
+        events.clear();
+
+        self.sensor_volumes.retain(|volume_handle, _| colliders.get(*volume_handle).is_some());
+
+        for (volume_handle, inside) in self.sensor_volumes.iter_mut() {
+            let volume = colliders.get(*volume_handle).unwrap();
+            let bounds = volume.shape().aabb(volume.position());
+
+            // Only what the broad phase finds overlapping the volume's bounds needs an exact test.
+            let now_inside: HashSet<_> = geometrical_world
+                .interferences_with_aabb(colliders, &bounds, volume.collision_groups())
+                .filter(|(_, collider)| !collider.is_sensor() && collider.body() != volume.body())
+                .filter(|(_, collider)| {
+                    query::proximity(volume.position(), volume.shape(), collider.position(), collider.shape(), 0.0)
+                        == Proximity::Intersecting
+                })
+                .map(|(handle, _)| handle)
+                .collect();
+
+            for collider in now_inside.difference(inside) {
+                events.push(VolumeEvent::Entered {
+                    volume: *volume_handle,
+                    collider: *collider,
+                });
+            }
+
+            for collider in inside.difference(&now_inside) {
+                events.push(VolumeEvent::Left {
+                    volume: *volume_handle,
+                    collider: *collider,
+                });
+            }
+
+            *inside = now_inside;
+        }
+    }
+
+    pub fn one_way_groups() -> CollisionGroups {
+        CollisionGroups::new().with_membership(&[ONE_WAY_GROUP])
+    }
+
+    /// Whether the collider currently passes through the one-way collider.
+    pub fn passes_one_way(&self, collider_handle: DefaultColliderHandle, one_way_handle: DefaultColliderHandle) -> bool {
+        self.one_way_passes
+            .read()
+            .map_or(false, |passes| passes.contains(&(collider_handle, one_way_handle)))
+    }
+
+    /// Whether the collider, like a feet sensor, touches a one-way collider.
+    pub fn on_one_way(&self, collider_handle: DefaultColliderHandle) -> bool {
+        self.colliders
+            .iter()
+            .filter(|(_, other)| is_one_way(other.collision_groups()))
+            .any(|(one_way_handle, _)| self.colliders_touching(collider_handle, one_way_handle))
+    }
+
+    /// Contact filter for one-way colliders, decided for each of them on its own: the collider
+    /// passes through them while its body moves up, while it is below their surface, or when it
+    /// drops through them on purpose.
+    pub fn filter_one_way_contacts(&mut self, collider_handle: DefaultColliderHandle, drop_through: bool) {
+        let passing: HashSet<DefaultColliderHandle> = match self.colliders.get(collider_handle) {
+            Some(collider) => {
+                let moving_up = self
+                    .bodies
+                    .rigid_body(collider.body())
+                    .map_or(false, |rigid_body| rigid_body.velocity().linear.y > 1.0);
+
+                let aabb = collider.shape().aabb(collider.position());
+
+                self.colliders
+                    .iter()
+                    .filter(|(_, other)| is_one_way(other.collision_groups()))
+                    .filter(|(_, other)| {
+                        let surface = other.shape().aabb(other.position());
+
+                        let below_surface = aabb.mins().x < surface.maxs().x
+                            && aabb.maxs().x > surface.mins().x
+                            && aabb.maxs().y > surface.mins().y
+                            && aabb.mins().y < surface.maxs().y - ONE_WAY_TOLERANCE;
+
+                        drop_through || moving_up || below_surface
+                    })
+                    .map(|(handle, _)| handle)
+                    .collect()
+            }
+            None => return,
+        };
+
+        {
+            let mut passes = match self.one_way_passes.write() {
+                Ok(passes) => passes,
+                Err(_) => return,
+            };
+
+            let previous: HashSet<DefaultColliderHandle> = passes
+                .iter()
+                .filter(|&&(collider, _)| collider == collider_handle)
+                .map(|&(_, one_way)| one_way)
+                .collect();
+
+            if previous == passing {
+                return;
+            }
+
+            passes.retain(|&(collider, _)| collider != collider_handle);
+            passes.extend(passing.into_iter().map(|one_way| (collider_handle, one_way)));
+        }
+
+        // Setting the groups again has the broad phase run its pairs of the collider through the filter anew.
+        if let Some(collider) = self.colliders.get_mut(collider_handle) {
+            let groups = *collider.collision_groups();
+            collider.set_collision_groups(groups);
+        }
+    }
+
+    /// Drops the one-way passes involving a collider about to be removed.
+    fn forget_one_way_passes(&mut self, collider_handle: DefaultColliderHandle) {
+        if let Ok(mut passes) = self.one_way_passes.write() {
+            passes.retain(|&(collider, one_way)| collider != collider_handle && one_way != collider_handle);
+        }
+    }
+
+    pub fn remove_collider(&mut self, collider_handle: DefaultColliderHandle) {
+        self.forget_one_way_passes(collider_handle);
+        self.colliders.remove(collider_handle);
+    }
+
+    /// Replaces the collider of a body with one built from `collider_desc`, keeping its collision groups,
+    /// material and tag.
+    pub fn swap_collider(&mut self, body: &mut Body, mut collider_desc: ColliderDesc<f32>) {
+        let mut groups = None;
+        let mut user_data = None;
+
+        if let Some(old) = self.colliders.get_mut(body.collider_handle) {
+            groups = Some(*old.collision_groups());
+            user_data = old.take_user_data();
+
+            // Materials are only shared through handles, so the new collider gets a copy of the old one.
+            if let Some(material) = old.material().downcast_ref::<BasicMaterial<f32>>() {
+                collider_desc.set_material(MaterialHandle::new(*material));
+            }
+        }
+
+        self.forget_one_way_passes(body.collider_handle);
+        self.colliders.remove(body.collider_handle);
+
+        let mut collider = collider_desc.build(BodyPartHandle(body.rigid_body_handle, 0));
+
+        if let Some(groups) = groups {
+            collider.set_collision_groups(groups);
+        }
+
+        collider.set_user_data(user_data);
+
+        body.collider_handle = self.colliders.insert(collider);
+    }
+
+    pub fn remove_body(&mut self, body: &Body) {
+        self.forget_one_way_passes(body.collider_handle);
+        self.colliders.remove(body.collider_handle);
+        self.bodies.remove(body.rigid_body_handle);
+    }
+
+    /// Picks up `carried`, whose collider then follows the one of `carrier` around at `offset` from
+    /// it until released. Calling it again for a body that's already carried only updates the offset.
+    pub fn carry(&mut self, carrier: &Body, carried: &Body, offset: Vector2<f32>) {
+        if let Some(rigid_body) = self.bodies.rigid_body_mut(carried.rigid_body_handle) {
+            rigid_body.set_status(BodyStatus::Kinematic);
+            self.carried.insert(
+                carried.rigid_body_handle,
+                Carried {
+                    carrier: carrier.rigid_body_handle,
+                    carrier_collider: carrier.collider_handle,
+                    collider: carried.collider_handle,
+                    offset,
+                },
+            );
+        }
+    }
+
+    /// Lets go of a carried body, sending it off at `velocity`.
+    pub fn release(&mut self, carried: &Body, velocity: Vector2<f32>) {
+        self.carried.remove(&carried.rigid_body_handle);
+
+        if let Some(rigid_body) = self.bodies.rigid_body_mut(carried.rigid_body_handle) {
+            rigid_body.set_status(BodyStatus::Dynamic);
+            rigid_body.set_linear_velocity(velocity);
+        }
+    }
+
+    /// Gives carried bodies the velocity that keeps them at their carrier during the next step.
+    fn move_carried(&mut self) {
+        let delta = self.mechanical_world.timestep();
+        let mut dropped = Vec::new();
+
+        for (handle, carried) in self.carried.iter() {
+            let carrier = self.bodies.rigid_body(carried.carrier);
+            let carrier_collider = self.colliders.get(carried.carrier_collider);
+            let collider = self.colliders.get(carried.collider);
+
+            let (target, position) = match (carrier, carrier_collider, collider) {
+                (Some(carrier), Some(carrier_collider), Some(collider)) => (
+                    carrier_collider.position().translation.vector + carrier.velocity().linear * delta + carried.offset,
+                    collider.position().translation.vector,
+                ),
+                _ => {
+                    dropped.push(*handle);
+                    continue;
+                }
+            };
+
+            if let Some(rigid_body) = self.bodies.rigid_body_mut(*handle) {
+                rigid_body.set_linear_velocity((target - position) / delta);
+            }
+        }
+
+        // Whatever was held by a body that's gone is dropped where it is.
+        for handle in dropped {
+            self.carried.remove(&handle);
+
+            if let Some(rigid_body) = self.bodies.rigid_body_mut(handle) {
+                rigid_body.set_status(BodyStatus::Dynamic);
+            }
         }
     }
+
+    /// Whether the two colliders touch or overlap. This is a geometric test, so unlike contacts it
+    /// also covers pairs the physics world never checks, like kinematic against static bodies.
+    pub fn colliders_touching(
+        &self,
+        collider_handle: DefaultColliderHandle,
+        other_handle: DefaultColliderHandle,
+    ) -> bool {
+        match (self.colliders.get(collider_handle), self.colliders.get(other_handle)) {
+            (Some(collider), Some(other)) => {
+                query::proximity(
+                    collider.position(),
+                    collider.shape(),
+                    other.position(),
+                    other.shape(),
+                    TOUCHING_DISTANCE,
+                ) != Proximity::Disjoint
+            }
+            _ => false,
+        }
+    }
+
+    /// Sweeps the collider of a character along `translation`, sliding along whatever it hits.
+    /// Only static and kinematic colliders block it, dynamic bodies are left for the physics
+    /// world to push around. When it ends up in the air, it's pulled down onto ground that is at
+    /// most `snap_distance` below, so it sticks to slopes and steps instead of flying off them.
+    pub fn move_character(
+        &self,
+        collider_handle: DefaultColliderHandle,
+        translation: Vector2<f32>,
+        drop_through: bool,
+        snap_distance: f32,
+    ) -> CharacterMove {
+        let collider = match self.colliders.get(collider_handle) {
+            Some(collider) => collider,
+            // Without a collider nothing is in the way.
+            None => return CharacterMove::unobstructed(translation),
+        };
+
+        let mut position = *collider.position();
+        let mut remaining = translation;
+        let mut result = CharacterMove::unobstructed(Vector2::zeros());
+
+        for _ in 0..CHARACTER_MAX_SLIDES {
+            if remaining.norm() < std::f32::EPSILON {
+                break;
+            }
+
+            let (travel, hit) = self.sweep(collider, &position, &remaining, drop_through);
+            let moved = remaining * travel;
+
+            position.translation.vector += moved;
+            result.translation += moved;
+            remaining -= moved;
+
+            let (body_handle, normal) = match hit {
+                Some(hit) => hit,
+                None => break,
+            };
+
+            if normal.y >= WALKABLE_NORMAL_Y {
+                result.ground = Some((body_handle, normal));
+
+                // Keep the horizontal speed on the ground, whatever its slope.
+                let tangent = Vector2::new(normal.y, -normal.x);
+                remaining = tangent * (remaining.x / tangent.x);
+            } else {
+                if normal.y <= -WALKABLE_NORMAL_Y {
+                    result.ceiling = true;
+                } else {
+                    result.wall = Some(normal);
+                }
+
+                remaining -= normal * remaining.dot(&normal);
+            }
+        }
+
+        if result.ground.is_none() && snap_distance > 0.0 {
+            let down = Vector2::new(0.0, -snap_distance);
+
+            if let (travel, Some((body_handle, normal))) = self.sweep(collider, &position, &down, drop_through) {
+                if normal.y >= WALKABLE_NORMAL_Y {
+                    result.translation += down * travel;
+                    result.ground = Some((body_handle, normal));
+                }
+            }
+        }
+
+        result
+    }
+
+    /// How far along `translation` the collider gets from `position` before hitting something,
+    /// as a fraction, together with the body and surface normal of what it hits first.
+    fn sweep(
+        &self,
+        collider: &Collider<f32, DefaultBodyHandle>,
+        position: &Isometry2<f32>,
+        translation: &Vector2<f32>,
+        drop_through: bool,
+    ) -> (f32, Option<(DefaultBodyHandle, Vector2<f32>)>) {
+        let length = translation.norm();
+        let mut travel = 1.0;
+        let mut hit = None;
+
+        let bottom = collider.shape().aabb(position).mins().y;
+
+        for (_, other) in self.colliders.iter() {
+            if other.body() == collider.body() || other.is_sensor() {
+                continue;
+            }
+
+            // What a character carries moves along with it rather than getting in its way.
+            let carried_by_self = self
+                .carried
+                .get(&other.body())
+                .map_or(false, |carried| carried.carrier == collider.body());
+
+            if carried_by_self {
+                continue;
+            }
+
+            let dynamic = self
+                .bodies
+                .rigid_body(other.body())
+                .map_or(false, |rigid_body| rigid_body.status() == BodyStatus::Dynamic);
+
+            if dynamic {
+                continue;
+            }
+
+            if is_one_way(other.collision_groups()) {
+                let surface = other.shape().aabb(other.position());
+
+                if drop_through || translation.y >= 0.0 || bottom < surface.maxs().y - ONE_WAY_TOLERANCE {
+                    continue;
+                }
+            }
+
+            let impact = query::time_of_impact(
+                position,
+                translation,
+                collider.shape(),
+                other.position(),
+                &Vector2::zeros(),
+                other.shape(),
+                1.0,
+                0.0,
+            );
+
+            if let Some(impact) = impact {
+                let normal = -impact.normal1.into_inner();
+
+                // Already touching something it's moving away from.
+                if translation.dot(&normal) >= 0.0 {
+                    continue;
+                }
+
+                let fraction = ((impact.toi * length - CHARACTER_SKIN) / length).max(0.0);
+
+                if hit.is_none() || fraction < travel {
+                    travel = fraction;
+                    hit = Some((other.body(), normal));
+                }
+            }
+        }
+
+        (travel, hit)
+    }
+}
+
+/// Whether colliders in the groups can only be landed on from above. Ordinary colliders are
+/// members of every group, `ONE_WAY_GROUP` included, so that alone doesn't tell them apart.
+fn is_one_way(groups: &CollisionGroups) -> bool {
+    groups.is_member_of(ONE_WAY_GROUP) && !groups.is_member_of(SOLID_GROUP)
+}
+
+impl Default for PhysicsWorld {
+    fn default() -> Self {
+        Self::new(&PhysicsProfile::default())
+    }
 }
@@ -1,20 +1,40 @@
-use std::collections::{HashMap, HashSet, vec_deque::VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, vec_deque::VecDeque};
 use std::time::{Duration, Instant};
 
 use ggez::{
     event::{KeyCode, KeyMods},
     graphics::{DrawParam, Mesh},
+    nalgebra::Point2,
 };
+use serde::Deserialize;
+use specs::Entity;
+
+use crate::components::WarpDestination;
+
+use super::{assets::TextureHandle, game::TARGET_FPS};
 
 #[derive(Default)]
 pub struct DeltaTime(pub f64);
 
+/// Sprites to draw this frame, by render layer depth and texture, drawn from the lowest depth up.
 #[derive(Default)]
-pub struct Renderables(pub VecDeque<DrawParam>);
+pub struct Renderables(pub BTreeMap<(i32, TextureHandle), VecDeque<DrawParam>>);
 
 #[derive(Default)]
 pub struct DebugRenderables(pub VecDeque<Mesh>);
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum AnimationEvent {
+    /// A clip played once has reached its last frame.
+    Finished { entity: Entity, state: String },
+    /// A frame named in the animation graph came up, like a footstep.
+    Raised { entity: Entity, name: String },
+}
+
+/// Events raised by the animation system, kept until it runs again on the next tick.
+#[derive(Default)]
+pub struct AnimationEvents(pub Vec<AnimationEvent>);
+
 #[derive(Default)]
 pub struct InputEvents {
     pub pressed_keys: HashMap<KeyCode, Instant>,
@@ -38,3 +58,141 @@ impl InputEvents {
         self.is_key_pressed(key) && self.key_hold_time(key).unwrap().elapsed().as_millis() < 100
     }
 }
+
+/// Seconds left on the clock when the level timer starts hurrying the player.
+pub const HURRY_UP_SECONDS: u32 = 100;
+
+/// Points awarded for every second left on the clock at the goal.
+pub const TIME_BONUS: u32 = 50;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimerState {
+    Stopped,
+    Running,
+    HurryUp,
+    Expired,
+}
+
+/// Level time limit, counted down once per simulation tick.
+#[derive(Clone, Copy, Debug)]
+pub struct LevelTimer {
+    pub ticks_left: u32,
+    pub state: TimerState,
+}
+
+impl LevelTimer {
+    pub fn new(seconds: u32) -> Self {
+        let mut timer = Self {
+            ticks_left: seconds * TARGET_FPS,
+            state: TimerState::Running,
+        };
+        timer.update_state();
+        timer
+    }
+
+    pub fn seconds_left(&self) -> u32 {
+        (self.ticks_left + TARGET_FPS - 1) / TARGET_FPS
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state == TimerState::Running || self.state == TimerState::HurryUp
+    }
+
+    pub fn stop(&mut self) {
+        if self.is_running() {
+            self.state = TimerState::Stopped;
+        }
+    }
+
+    /// Advances the timer by a single tick and returns the resulting state.
+    pub fn tick(&mut self) -> TimerState {
+        if self.is_running() {
+            self.ticks_left = self.ticks_left.saturating_sub(1);
+            self.update_state();
+        }
+
+        self.state
+    }
+
+    /// Drains up to `seconds` from the clock and returns the points they are worth.
+    pub fn tally(&mut self, seconds: u32) -> u32 {
+        let seconds = seconds.min(self.seconds_left());
+        self.ticks_left = self.ticks_left.saturating_sub(seconds * TARGET_FPS);
+
+        seconds * TIME_BONUS
+    }
+
+    fn update_state(&mut self) {
+        if self.ticks_left == 0 {
+            self.state = TimerState::Expired;
+        } else if self.seconds_left() <= HURRY_UP_SECONDS {
+            self.state = TimerState::HurryUp;
+        }
+    }
+}
+
+impl Default for LevelTimer {
+    fn default() -> Self {
+        Self {
+            ticks_left: 0,
+            state: TimerState::Stopped,
+        }
+    }
+}
+
+pub const STARTING_LIVES: u32 = 5;
+
+#[derive(Clone, Debug)]
+pub struct CheckpointState {
+    pub area: String,
+    pub position: Point2<f32>,
+}
+
+/// Player progress that outlives a single scene.
+#[derive(Clone, Debug)]
+pub struct GameSession {
+    pub score: u32,
+    pub lives: u32,
+    /// Respawn point of the last checkpoint reached in the current level.
+    pub checkpoint: Option<CheckpointState>,
+}
+
+impl Default for GameSession {
+    fn default() -> Self {
+        Self {
+            score: 0,
+            lives: STARTING_LIVES,
+            checkpoint: None,
+        }
+    }
+}
+
+/// World-space extents of the current level.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub struct LevelBounds {
+    pub left: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub top: f32,
+}
+
+/// Names of the level and area the scene was built from.
+#[derive(Clone, Debug, Default)]
+pub struct LevelInfo {
+    pub level: String,
+    pub area: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneTransition {
+    /// Reload the current level, starting from the last checkpoint if one was reached.
+    Restart,
+    /// Load the level following the current one.
+    NextLevel,
+    /// Move the player to another area through a pipe.
+    Warp(WarpDestination),
+}
+
+/// Transition requested by a system, handled by the game once the tick is over.
+#[derive(Default)]
+pub struct PendingTransition(pub Option<SceneTransition>);
@@ -3,7 +3,7 @@ use std::time::{Duration, Instant};
 use ggez::{
     Context,
     event::KeyCode,
-    graphics::{self, MeshBuilder, spritebatch::SpriteBatch},
+    graphics::{self, MeshBuilder},
     input::keyboard,
     nalgebra::Point2, timer,
 };
@@ -12,15 +12,26 @@ use graphics::DrawParam;
 use specs::{Dispatcher, Join};
 use specs::{RunNow, World, WorldExt};
 
-use crate::components::Body;
-use crate::systems::RenderingSystem;
+use crate::components::{Animation, Body, Goal, Player, PowerState, Transform};
+use crate::systems::{CameraSystem, RenderingSystem, TransformHistorySystem};
 
 use super::{
+    assets::Textures,
     camera::Camera,
+    game::interpolation_alpha,
     physics::PhysicsWorld,
-    resources::{DebugRenderables, DeltaTime, InputEvents, Renderables},
+    resources::{
+        DebugRenderables, DeltaTime, GameSession, InputEvents, LevelTimer, PendingTransition,
+        Renderables, SceneTransition,
+    },
+    tuning::Tuning,
 };
 
+/// Ticks the level intro takes to pan over to the goal, and back.
+const INTRO_PAN_TICKS: u32 = 90;
+/// Ticks the level intro lingers on either end of the pan.
+const INTRO_HOLD_TICKS: u32 = 45;
+
 pub struct SceneManager<'a, 'b> {
     scenes: Vec<Box<Scene<'a, 'b>>>,
 }
@@ -40,6 +51,10 @@ impl<'a, 'b> SceneManager<'a, 'b> {
         self.scenes.push(Box::new(scene));
     }
 
+    pub fn replace_scene(&mut self, scene: Scene<'a, 'b>) {
+        *self.current_scene() = scene;
+    }
+
     pub fn pop_scene(&'a mut self) -> Option<Box<Scene<'a, 'b>>> {
         self.scenes.pop()
     }
@@ -48,11 +63,20 @@ impl<'a, 'b> SceneManager<'a, 'b> {
         self.current_scene().update(ctx)
     }
 
-    pub fn draw(&mut self, ctx: &mut Context, batch: &mut SpriteBatch) -> GameResult<()> {
-        self.current_scene().draw(ctx, batch)
+    pub fn draw(&mut self, ctx: &mut Context, textures: &mut Textures) -> GameResult<()> {
+        self.current_scene().draw(ctx, textures)
     }
 }
 
+/// Player state handed from a scene to the one replacing it.
+#[derive(Clone, Debug, Default)]
+pub struct PlayerHandoff {
+    pub session: GameSession,
+    pub power: PowerState,
+    /// Timer to keep running, or `None` to start the level clock over.
+    pub timer: Option<LevelTimer>,
+}
+
 pub struct Scene<'a, 'b> {
     dispatcher: Dispatcher<'a, 'b>,
     world: World,
@@ -69,6 +93,7 @@ impl<'a, 'b> Scene<'a, 'b> {
         world.insert(Camera::default());
         world.insert(InputEvents::default());
         world.insert(DebugRenderables::default());
+        world.insert(PendingTransition::default());
         world.insert(physics_world);
 
 
@@ -79,6 +104,81 @@ impl<'a, 'b> Scene<'a, 'b> {
         }
     }
 
+    pub fn take_transition(&mut self) -> Option<SceneTransition> {
+        self.world.write_resource::<PendingTransition>().0.take()
+    }
+
+    pub fn handoff(&self) -> PlayerHandoff {
+        let player_storage = self.world.read_storage::<Player>();
+        let power_storage = self.world.read_storage::<PowerState>();
+
+        let power = (&player_storage, &power_storage)
+            .join()
+            .map(|(_, power)| *power)
+            .next()
+            .unwrap_or_default();
+
+        PlayerHandoff {
+            session: (*self.world.read_resource::<GameSession>()).clone(),
+            power,
+            timer: Some(*self.world.read_resource::<LevelTimer>()),
+        }
+    }
+
+    /// Hands the player state over to the entities of a freshly built scene.
+    pub fn receive(&mut self, handoff: PlayerHandoff) {
+        {
+            let player_storage = self.world.read_storage::<Player>();
+            let mut power_storage = self.world.write_storage::<PowerState>();
+
+            for (_, power) in (&player_storage, &mut power_storage).join() {
+                *power = handoff.power;
+            }
+        }
+
+        if let Some(timer) = handoff.timer {
+            self.world.insert(timer);
+        }
+
+        self.world.insert(handoff.session);
+    }
+
+    /// Shows where the level ends before the player gets going, panning the camera over to the goal
+    /// and back. The game, level clock included, is paused until it finishes or is skipped with Enter.
+    pub fn play_intro(&mut self) {
+        let transform_storage = self.world.read_storage::<Transform>();
+        let goal_storage = self.world.read_storage::<Goal>();
+        let player_storage = self.world.read_storage::<Player>();
+
+        let goal = (&transform_storage, &goal_storage).join().map(|(transform, _)| transform.position).next();
+        let start = (&transform_storage, &player_storage).join().map(|(transform, _)| transform.position).next();
+
+        if let (Some(goal), Some(start)) = (goal, start) {
+            let mut camera = self.world.write_resource::<Camera>();
+
+            camera.jump_to(start);
+            camera.hold(INTRO_HOLD_TICKS, true);
+            camera.pan_to(goal, INTRO_PAN_TICKS, true);
+            camera.hold(INTRO_HOLD_TICKS, true);
+            camera.pan_to(start, INTRO_PAN_TICKS, true);
+        }
+    }
+
+    /// Swaps in reloaded tuning, updating whatever was already set up from the previous one.
+    pub fn apply_tuning(&mut self, tuning: Tuning) {
+        self.world.write_resource::<PhysicsWorld>().apply_profile(&tuning.physics);
+
+        {
+            let mut animation_storage = self.world.write_storage::<Animation>();
+
+            for animation in (&mut animation_storage).join() {
+                animation.speed_factor = tuning.animation.speed_factor;
+            }
+        }
+
+        self.world.insert(tuning);
+    }
+
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
         {
             let mut input_events = self.world.write_resource::<InputEvents>();
@@ -86,7 +186,8 @@ impl<'a, 'b> Scene<'a, 'b> {
             let mut delta = self.world.write_resource::<DeltaTime>();
             delta.0 = timer::delta(ctx).as_secs_f64();
 
-            cam.update();
+            let (width, height) = graphics::size(ctx);
+            cam.set_viewport(width, height);
 
             input_events.active_mods = keyboard::active_mods(ctx);
 
@@ -109,12 +210,22 @@ impl<'a, 'b> Scene<'a, 'b> {
             }
         }
 
-        self.dispatcher.dispatch(&self.world);
+        TransformHistorySystem.run_now(&self.world);
+
+        // Only the camera moves while one of its commands pauses the game, like the level intro.
+        let paused = self.world.read_resource::<Camera>().pauses_play();
+
+        if paused {
+            CameraSystem.run_now(&self.world);
+        } else {
+            self.dispatcher.dispatch(&self.world);
+        }
+
         self.world.maintain();
         Ok(())
     }
 
-    fn draw(&self, ctx: &mut Context, batch: &mut SpriteBatch) -> GameResult<()> {
+    fn draw(&self, ctx: &mut Context, textures: &mut Textures) -> GameResult<()> {
         {
             let mut render_system = RenderingSystem::new(ctx);
 
@@ -125,13 +236,22 @@ impl<'a, 'b> Scene<'a, 'b> {
         let mut debug_renderables = self.world.write_resource::<DebugRenderables>();
         let cam = self.world.read_resource::<Camera>();
 
-        while !renderables.0.is_empty() {
-            let renderable = renderables.0.pop_front().unwrap();
-            batch.add(renderable);
+        let alpha = interpolation_alpha(ctx);
+
+        // Every layer of every texture is its own batch, so layers stack in order whatever order
+        // sprites were added in.
+        for (&(_, texture), layer) in renderables.0.iter_mut() {
+            let batch = textures.batch_mut(texture);
+
+            while let Some(renderable) = layer.pop_front() {
+                batch.add(renderable);
+            }
+
+            cam.render(ctx, batch, alpha)?;
+            batch.clear();
         }
 
-        cam.render(ctx, batch)?;
-        cam.debug_render(ctx, &mut debug_renderables.0)?;
+        cam.debug_render(ctx, &mut debug_renderables.0, alpha)?;
 
         Ok(())
     }
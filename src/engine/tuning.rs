@@ -0,0 +1,242 @@
+use std::io::Read;
+
+use ggez::{Context, filesystem, GameError, GameResult};
+use serde::Deserialize;
+
+const TUNING_PATH: &str = "/tuning.json";
+/// Ticks between checks of the tuning file for changes.
+const TUNING_POLL_TICKS: u32 = 60;
+
+/// Values that decide how the game feels, loaded from `resources/tuning.json` so they can be
+/// tuned without a rebuild. Anything left out of the file keeps its default.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Tuning {
+    pub physics: PhysicsProfile,
+    pub movement: MovementProfile,
+    pub animation: AnimationProfile,
+    pub enemy: EnemyProfile,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct PhysicsProfile {
+    /// Vertical acceleration of everything in the physics world.
+    pub gravity: f32,
+    /// Fastest anything can fall.
+    pub terminal_fall_speed: f32,
+    /// Gravity scale of anything in water.
+    pub water_gravity_scale: f32,
+    /// Fraction of its speed a body in water loses per second.
+    pub water_drag: f32,
+    /// Fastest anything sinks in water.
+    pub water_sink_speed: f32,
+}
+
+impl Default for PhysicsProfile {
+    fn default() -> Self {
+        Self {
+            gravity: -450.0,
+            terminal_fall_speed: 400.0,
+            water_gravity_scale: 0.3,
+            water_drag: 2.0,
+            water_sink_speed: 60.0,
+        }
+    }
+}
+
+/// How the player moves, speeds are in pixels per second.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct MovementProfile {
+    pub walk_speed: f32,
+    pub run_speed: f32,
+    pub ground_acceleration: f32,
+    pub air_acceleration: f32,
+    /// How fast the player slows down without any input.
+    pub ground_deceleration: f32,
+    pub air_deceleration: f32,
+    /// How fast the player slows down when pushing against its direction of travel on the ground.
+    pub skid_deceleration: f32,
+    /// Upward speed a jump starts with.
+    pub jump_velocity: f32,
+    pub run_jump_velocity: f32,
+    /// Upward speed a jump is cut down to when the jump button is let go early.
+    pub jump_cut_velocity: f32,
+    /// Vertical speed under which a held jump counts as being at its apex.
+    pub apex_speed: f32,
+    /// Gravity scale at the apex of a held jump, to hang in the air a little longer.
+    pub apex_gravity_scale: f32,
+    /// Gravity scale while falling, so jumps come down faster than they go up.
+    pub fall_gravity_scale: f32,
+    /// Ticks after walking off a ledge in which the player can still jump.
+    pub coyote_ticks: u32,
+    /// Ticks a jump press made in the air is remembered, to jump as soon as the player lands.
+    pub jump_buffer_ticks: u32,
+    /// How fast a crouching player slides to a stop.
+    pub crouch_slide_deceleration: f32,
+    pub spin_jump_velocity: f32,
+    /// Upward speed of a spin jump bouncing off a spiky enemy.
+    pub spin_bounce_velocity: f32,
+    /// Ticks a ground pound hangs in the air before slamming down.
+    pub ground_pound_hang_ticks: u32,
+    pub ground_pound_speed: f32,
+    /// Fastest the player slides down a wall it pushes into.
+    pub wall_slide_speed: f32,
+    pub wall_jump_velocity: f32,
+    /// Sideways speed a wall jump pushes the player away from the wall with.
+    pub wall_jump_push: f32,
+    /// How far below the player the ground can drop away and still have it stick to the ground.
+    pub ground_snap_distance: f32,
+    /// Top sideways speed in water, whether or not the run button is held.
+    pub swim_speed: f32,
+    /// Upward speed each press of the jump button gives the player in water.
+    pub swim_stroke_velocity: f32,
+    /// Speed of climbing vines and ladders, in every direction.
+    pub climb_speed: f32,
+    /// Sideways speed a thrown object gets on top of the player's own.
+    pub throw_speed: f32,
+    /// Upward speed a thrown object gets, to arc a little rather than skim the ground.
+    pub throw_lift: f32,
+    /// Upward speed of an object thrown while holding up.
+    pub throw_up_velocity: f32,
+}
+
+impl Default for MovementProfile {
+    fn default() -> Self {
+        Self {
+            walk_speed: 100.0,
+            run_speed: 240.0,
+            ground_acceleration: 400.0,
+            air_acceleration: 300.0,
+            ground_deceleration: 300.0,
+            air_deceleration: 100.0,
+            skid_deceleration: 900.0,
+            jump_velocity: 230.0,
+            run_jump_velocity: 260.0,
+            jump_cut_velocity: 100.0,
+            apex_speed: 40.0,
+            apex_gravity_scale: 0.5,
+            fall_gravity_scale: 1.8,
+            coyote_ticks: 6,
+            jump_buffer_ticks: 6,
+            crouch_slide_deceleration: 150.0,
+            spin_jump_velocity: 210.0,
+            spin_bounce_velocity: 200.0,
+            ground_pound_hang_ticks: 12,
+            ground_pound_speed: 400.0,
+            wall_slide_speed: 60.0,
+            wall_jump_velocity: 230.0,
+            wall_jump_push: 120.0,
+            ground_snap_distance: 8.0,
+            swim_speed: 60.0,
+            swim_stroke_velocity: 120.0,
+            climb_speed: 60.0,
+            throw_speed: 180.0,
+            throw_lift: 40.0,
+            throw_up_velocity: 320.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct AnimationProfile {
+    /// Playback rate of walk cycles per pixel per second of speed, 1 being the authored frame durations.
+    pub speed_factor: f32,
+    /// Speed against the facing direction above which a body is drawn skidding.
+    pub drag_threshold: f32,
+}
+
+impl Default for AnimationProfile {
+    fn default() -> Self {
+        Self {
+            speed_factor: 0.0125,
+            drag_threshold: 10.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct EnemyProfile {
+    pub walk_speed: f32,
+    pub acceleration: f32,
+    /// Top sideways speed in water, whether or not the run button is held.
+    pub swim_speed: f32,
+    /// Ticks a swimming enemy keeps going one way before turning around.
+    pub swim_turn_ticks: u32,
+}
+
+impl Default for EnemyProfile {
+    fn default() -> Self {
+        Self {
+            walk_speed: 50.0,
+            acceleration: 10000.0,
+            swim_speed: 30.0,
+            swim_turn_ticks: 180,
+        }
+    }
+}
+
+/// The tuning file along with what it last contained, so edits to it get picked up.
+pub struct TuningFile {
+    pub tuning: Tuning,
+    source: String,
+    ticks: u32,
+}
+
+impl TuningFile {
+    pub fn load(ctx: &mut Context) -> GameResult<Self> {
+        let source = Self::read(ctx)?;
+        let tuning = Self::parse(&source)?;
+
+        Ok(Self {
+            tuning,
+            source,
+            ticks: 0,
+        })
+    }
+
+    /// Checks the file every so often, returning the new tuning once its contents change.
+    /// A file that doesn't parse is returned as an error once and the previous tuning kept.
+    pub fn poll(&mut self, ctx: &mut Context) -> GameResult<Option<Tuning>> {
+        self.ticks += 1;
+
+        if self.ticks < TUNING_POLL_TICKS {
+            return Ok(None);
+        }
+
+        self.ticks = 0;
+
+        let source = Self::read(ctx)?;
+
+        if source == self.source {
+            return Ok(None);
+        }
+
+        let tuning = Self::parse(&source);
+        self.source = source;
+
+        self.tuning = tuning?;
+
+        Ok(Some(self.tuning))
+    }
+
+    fn read(ctx: &mut Context) -> GameResult<String> {
+        let mut file = filesystem::open(ctx, TUNING_PATH)?;
+        let mut source = String::new();
+
+        file.read_to_string(&mut source).map_err(|e| {
+            GameError::ResourceLoadError(format!("Failed to read tuning: {}", e))
+        })?;
+
+        Ok(source)
+    }
+
+    fn parse(source: &str) -> GameResult<Tuning> {
+        serde_json::from_str(source).map_err(|e| {
+            GameError::ResourceLoadError(format!("Failed to parse tuning: {}", e))
+        })
+    }
+}
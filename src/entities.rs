@@ -1,17 +1,31 @@
-use std::collections::HashMap;
 use std::f32::consts::PI;
 
-use ggez::graphics::Rect;
 use ggez::nalgebra::Point2;
 use nphysics2d::material::{BasicMaterial, MaterialHandle};
 use nphysics2d::nalgebra::base::Vector2;
 use nphysics2d::nalgebra::Isometry2;
-use nphysics2d::ncollide2d::shape::{Ball, Cuboid, ShapeHandle};
+use nphysics2d::nalgebra::Point2 as PhysicsPoint2;
+use nphysics2d::ncollide2d::shape::{Ball, ConvexPolygon, Cuboid, ShapeHandle};
 use nphysics2d::object::{BodyStatus, ColliderDesc, RigidBodyDesc};
 use specs::{Builder, Entity, World, WorldExt};
 
-use crate::components::{Animation, AnimationParams, AnimationStates, Body, CameraTarget, FeetSensor, Jumper, Player, Sprite, Transform, BasicAI};
-use crate::engine::physics::PhysicsWorld;
+use crate::components::{Animation, AnimationParams, Body, CameraTarget, FeetSensor, Jumper, Player, Sprite, Transform, BasicAI, Checkpoint, Goal};
+use crate::components::{MovingPlatform, Pipe, PipeDirection, PlatformPath, PowerState, WarpDestination};
+use crate::components::{Carryable, Carrier, RenderLayer};
+use crate::components::{Breakable, CharacterController, Crouch, GravityScale, SlopeAngle, SlopeDirection, SpecialMove, Spiky, SwimAI};
+use crate::engine::{animation::AnimationGraphs, assets::{SpriteFrame, TextureIndex}, physics::PhysicsWorld, tuning::Tuning};
+
+/// Atlas frames drawn by name from code rather than level data, checked once the textures load.
+pub const SPRITE_FRAMES: [&str; 8] = [
+    "mario-stand",
+    "block",
+    "koopa-walk-1",
+    "cheep-cheep-swim-1",
+    "pole",
+    "water",
+    "shell",
+    "dust",
+];
 
 pub struct Mario;
 
@@ -21,69 +35,39 @@ impl Mario {
         position: Point2<f32>,
         physics_world: &mut PhysicsWorld,
     ) -> Entity {
+        // Moved by its character controller, the physics world only uses it to push props around.
         let rigid_body = RigidBodyDesc::new()
             .position(Isometry2::new(Vector2::new(position.x, position.y), 0.0))
-            .mass(1.0)
-            .max_linear_velocity(1000.0)
-            .kinematic_rotations(true)
+            .status(BodyStatus::Kinematic)
             .build();
 
         let shape = ShapeHandle::new(Ball::new(8.0));
         let collider_desc =
-            ColliderDesc::new(shape).material(MaterialHandle::new(BasicMaterial::new(0.0, 0.0)));
+            ColliderDesc::new(shape.clone()).material(MaterialHandle::new(BasicMaterial::new(0.0, 0.0)));
 
         let body = physics_world.insert_body(rigid_body, collider_desc);
 
+        let crouch = Crouch {
+            crouching: false,
+            standing_shape: shape,
+            crouching_shape: ShapeHandle::new(Ball::new(5.0)),
+            crouching_offset: Vector2::new(0.0, -3.0),
+        };
+
         let rect_shape = ShapeHandle::new(Cuboid::new(Vector2::<f32>::new(7.0, 1.0)));
         let sensor_desc = ColliderDesc::new(rect_shape).translation(Vector2::new(0.0, -10.0)).user_data("feet");
 
         let sensor_handle = physics_world.insert_sensor(&body.rigid_body_handle, sensor_desc);
 
-        let mut animation = Animation::default();
-
-        animation.speed = 8.0;
-        animation.speed_factor = 0.1;
-
-        animation.animations.insert(
-            AnimationStates::Idle,
-            AnimationParams {
-                frame: 0.0,
-                frame_count: 1,
-                start_frame: 0,
-            },
-        );
-
-        animation.animations.insert(
-            AnimationStates::Moving,
-            AnimationParams {
-                frame: 0.0,
-                frame_count: 3,
-                start_frame: 1,
-            },
-        );
-
-        animation.animations.insert(
-            AnimationStates::Jumping,
-            AnimationParams {
-                frame: 0.0,
-                frame_count: 1,
-                start_frame: 5,
-            },
-        );
-
-        animation.animations.insert(
-            AnimationStates::Drag,
-            AnimationParams {
-                frame: 0.0,
-                frame_count: 1,
-                start_frame: 4,
-            },
-        );
+        let animation = build_animation(world, "mario");
+
+        let frame = sprite_frame(world, "mario-stand");
 
         world
             .create_entity()
             .with(Sprite {
-                src: Rect::new(0.0, 0.0, 0.167, 0.33),
+                texture: frame.texture,
+                src: frame.src,
                 width: 32f32,
                 height: 16f32,
                 repeat: Vector2::new(1, 1),
@@ -100,8 +84,16 @@ impl Mario {
             .with(FeetSensor {
                 collider_handle: sensor_handle,
                 on_floor: false,
+                ground_velocity: Vector2::zeros(),
+                ground_normal: Vector2::y(),
             })
             .with(Jumper::default())
+            .with(CharacterController::default())
+            .with(GravityScale::default())
+            .with(SpecialMove::default())
+            .with(crouch)
+            .with(Carrier::default())
+            .with(PowerState::default())
             .build()
     }
 }
@@ -113,6 +105,7 @@ impl Brick {
         world: &mut World,
         position: Point2<f32>,
         block_count: Vector2<u32>,
+        one_way: bool,
         physics_world: &mut PhysicsWorld,
     ) -> Entity {
         let (width, height) = (16f32, 16f32);
@@ -124,17 +117,24 @@ impl Brick {
             .build();
 
         let shape = ShapeHandle::new(Cuboid::new(Vector2::new(block_count.x as f32 * width / 2.0, block_count.y as f32 * height / 2.0)));
-        let collider_desc = ColliderDesc::new(shape)
+        let mut collider_desc = ColliderDesc::new(shape)
             .density(100.0)
             .translation(Vector2::new((block_count.x as f32 * width) / 2.0 - width / 2.0, (block_count.y as f32 * -height) / 2.0 + height / 2.0))
             .material(MaterialHandle::new(BasicMaterial::new(0.0, 0.2))).user_data("brick");
 
+        if one_way {
+            collider_desc = collider_desc.collision_groups(PhysicsWorld::one_way_groups());
+        }
+
         let body = physics_world.insert_body(rigid_body, collider_desc);
 
+        let frame = sprite_frame(world, "block");
+
         world
             .create_entity()
             .with(Sprite {
-                src: Rect::new(0.0, 0.34, 0.1, 0.33),
+                texture: frame.texture,
+                src: frame.src,
                 width,
                 height,
                 repeat: block_count,
@@ -149,6 +149,128 @@ impl Brick {
     }
 }
 
+/// A single block that spin jumps and ground pounds break.
+pub struct TurnBlock;
+
+impl TurnBlock {
+    pub fn add(
+        world: &mut World,
+        position: Point2<f32>,
+        physics_world: &mut PhysicsWorld,
+    ) -> Entity {
+        let (width, height) = (16f32, 16f32);
+
+        let rigid_body = RigidBodyDesc::new()
+            .position(Isometry2::new(Vector2::new(position.x, position.y), 0.0))
+            .status(BodyStatus::Static)
+            .build();
+
+        let shape = ShapeHandle::new(Cuboid::new(Vector2::new(width / 2.0, height / 2.0)));
+        let collider_desc = ColliderDesc::new(shape)
+            .material(MaterialHandle::new(BasicMaterial::new(0.0, 0.2)))
+            .user_data("brick");
+
+        let body = physics_world.insert_body(rigid_body, collider_desc);
+
+        let frame = sprite_frame(world, "block");
+
+        world
+            .create_entity()
+            .with(Sprite {
+                texture: frame.texture,
+                src: frame.src,
+                width,
+                height,
+                repeat: Vector2::new(1, 1),
+            })
+            .with(Transform {
+                position,
+                rotation: 0.0,
+                scale: Vector2::new(1.0, 1.0),
+            })
+            .with(body)
+            .with(Breakable)
+            .build()
+    }
+}
+
+/// Tiles that are only drawn, like distant hills behind the level or bushes in front of it.
+pub struct Scenery;
+
+impl Scenery {
+    pub fn add(
+        world: &mut World,
+        position: Point2<f32>,
+        frame: &str,
+        tile_count: Vector2<u32>,
+        layer: RenderLayer,
+    ) -> Entity {
+        let frame = sprite_frame(world, frame);
+
+        world
+            .create_entity()
+            .with(Sprite {
+                texture: frame.texture,
+                src: frame.src,
+                width: 16f32,
+                height: 16f32,
+                repeat: tile_count,
+            })
+            .with(Transform {
+                position,
+                rotation: 0.0,
+                scale: Vector2::new(1.0, 1.0),
+            })
+            .with(layer)
+            .build()
+    }
+}
+
+/// A block that can be carried around and thrown.
+pub struct ThrowBlock;
+
+impl ThrowBlock {
+    pub fn add(
+        world: &mut World,
+        position: Point2<f32>,
+        physics_world: &mut PhysicsWorld,
+    ) -> Entity {
+        let (width, height) = (16f32, 16f32);
+
+        let rigid_body = RigidBodyDesc::new()
+            .position(Isometry2::new(Vector2::new(position.x, position.y), 0.0))
+            .mass(20.0)
+            .max_linear_velocity(1000.0)
+            .kinematic_rotations(true)
+            .build();
+
+        let shape = ShapeHandle::new(Cuboid::new(Vector2::new(width / 2.0 - 1.0, height / 2.0 - 1.0)));
+        let collider_desc = ColliderDesc::new(shape).material(MaterialHandle::new(BasicMaterial::new(0.0, 0.4)));
+
+        let body = physics_world.insert_body(rigid_body, collider_desc);
+
+        let frame = sprite_frame(world, "block");
+
+        world
+            .create_entity()
+            .with(Sprite {
+                texture: frame.texture,
+                src: frame.src,
+                width,
+                height,
+                repeat: Vector2::new(1, 1),
+            })
+            .with(Transform {
+                position,
+                rotation: 0.0,
+                scale: Vector2::new(1.0, 1.0),
+            })
+            .with(body)
+            .with(Carryable)
+            .build()
+    }
+}
+
 pub struct KoopaTroopa;
 
 impl KoopaTroopa {
@@ -175,34 +297,16 @@ impl KoopaTroopa {
 
         let sensor_handle = physics_world.insert_sensor(&body.rigid_body_handle, sensor_desc);
 
-        let mut animation = Animation::default();
+        let animation = build_animation(world, "koopa");
 
-        animation.speed = 10.0;
-        animation.speed_factor = 0.1;
-
-        animation.animations.insert(
-            AnimationStates::Idle,
-            AnimationParams {
-                frame: 3.0,
-                frame_count: 1,
-                start_frame: 3,
-            },
-        );
-
-        animation.animations.insert(
-            AnimationStates::Moving,
-            AnimationParams {
-                frame: 3.0,
-                frame_count: 2,
-                start_frame: 3,
-            },
-        );
 
+        let frame = sprite_frame(world, "koopa-walk-1");
 
         world
             .create_entity()
             .with(Sprite {
-                src: Rect::new(0.67, 0.34, 0.167, 0.67),
+                texture: frame.texture,
+                src: frame.src,
                 width: 32f32,
                 height: 32f32,
                 repeat: Vector2::new(1, 1),
@@ -217,8 +321,540 @@ impl KoopaTroopa {
             .with(FeetSensor {
                 collider_handle: sensor_handle,
                 on_floor: false,
+                ground_velocity: Vector2::zeros(),
+                ground_normal: Vector2::y(),
             })
             .with(BasicAI)
             .build()
     }
 }
+
+/// A fish that swims back and forth in water and flops around out of it.
+pub struct CheepCheep;
+
+impl CheepCheep {
+    pub fn add(
+        world: &mut World,
+        position: Point2<f32>,
+        physics_world: &mut PhysicsWorld,
+    ) -> Entity {
+        let rigid_body = RigidBodyDesc::new()
+            .position(Isometry2::new(Vector2::new(position.x, position.y), 0.0))
+            .mass(50.0)
+            .max_linear_velocity(1000.0)
+            .kinematic_rotations(true)
+            .build();
+
+        let shape = ShapeHandle::new(Ball::new(6.0));
+        let collider_desc = ColliderDesc::new(shape).material(MaterialHandle::new(BasicMaterial::new(0.0, 0.0)));
+
+        let body = physics_world.insert_body(rigid_body, collider_desc);
+
+        let animation = build_animation(world, "cheep-cheep");
+
+        let frame = sprite_frame(world, "cheep-cheep-swim-1");
+
+        world
+            .create_entity()
+            .with(Sprite {
+                texture: frame.texture,
+                src: frame.src,
+                width: 16f32,
+                height: 16f32,
+                repeat: Vector2::new(1, 1),
+            })
+            .with(Transform {
+                position,
+                rotation: 0.0,
+                scale: Vector2::new(1.0, 1.0),
+            })
+            .with(body)
+            .with(animation)
+            .with(SwimAI::default())
+            .build()
+    }
+}
+
+/// A vine or ladder the player can grab by pressing up while in front of it.
+pub struct Vine;
+
+impl Vine {
+    pub fn add(
+        world: &mut World,
+        position: Point2<f32>,
+        tile_count: u32,
+        physics_world: &mut PhysicsWorld,
+    ) -> Entity {
+        let tile_size = 16f32;
+        let height = tile_count as f32 * tile_size;
+
+        let rigid_body = RigidBodyDesc::new()
+            .position(Isometry2::new(Vector2::new(position.x, position.y), 0.0))
+            .status(BodyStatus::Static)
+            .build();
+
+        // Sprites repeat downwards, so the vine is anchored at its top tile.
+        let shape = ShapeHandle::new(Cuboid::new(Vector2::new(4.0, height / 2.0)));
+        let collider_desc = ColliderDesc::new(shape)
+            .translation(Vector2::new(0.0, -height / 2.0 + tile_size / 2.0))
+            .user_data("climbable");
+
+        let body = physics_world.insert_sensor_volume(rigid_body, collider_desc);
+
+        let frame = sprite_frame(world, "pole");
+
+        world
+            .create_entity()
+            .with(Sprite {
+                texture: frame.texture,
+                src: frame.src,
+                width: tile_size,
+                height: tile_size,
+                repeat: Vector2::new(1, tile_count),
+            })
+            .with(Transform {
+                position,
+                rotation: 0.0,
+                scale: Vector2::new(1.0, 1.0),
+            })
+            .with(body)
+            .build()
+    }
+}
+
+/// A block of water, bodies inside of it swim rather than walk and jump.
+pub struct WaterZone;
+
+impl WaterZone {
+    pub fn add(
+        world: &mut World,
+        position: Point2<f32>,
+        block_count: Vector2<u32>,
+        physics_world: &mut PhysicsWorld,
+    ) -> Entity {
+        let (width, height) = (16f32, 16f32);
+
+        let rigid_body = RigidBodyDesc::new()
+            .position(Isometry2::new(Vector2::new(position.x, position.y), 0.0))
+            .status(BodyStatus::Static)
+            .build();
+
+        let shape = ShapeHandle::new(Cuboid::new(Vector2::new(block_count.x as f32 * width / 2.0, block_count.y as f32 * height / 2.0)));
+        let collider_desc = ColliderDesc::new(shape)
+            .translation(Vector2::new((block_count.x as f32 * width) / 2.0 - width / 2.0, (block_count.y as f32 * -height) / 2.0 + height / 2.0))
+            .user_data("water");
+
+        let body = physics_world.insert_sensor_volume(rigid_body, collider_desc);
+
+        let frame = sprite_frame(world, "water");
+
+        // Drawn over whatever swims in it.
+        world
+            .create_entity()
+            .with(Sprite {
+                texture: frame.texture,
+                src: frame.src,
+                width,
+                height,
+                repeat: block_count,
+            })
+            .with(Transform {
+                position,
+                rotation: 0.0,
+                scale: Vector2::new(1.0, 1.0),
+            })
+            .with(RenderLayer { depth: 1, parallax: 1.0 })
+            .with(body)
+            .build()
+    }
+}
+
+/// An empty koopa shell, which slides along the ground when kicked.
+pub struct Shell;
+
+impl Shell {
+    pub fn add(
+        world: &mut World,
+        position: Point2<f32>,
+        physics_world: &mut PhysicsWorld,
+    ) -> Entity {
+        let rigid_body = RigidBodyDesc::new()
+            .position(Isometry2::new(Vector2::new(position.x, position.y), 0.0))
+            .mass(20.0)
+            .max_linear_velocity(1000.0)
+            .kinematic_rotations(true)
+            .build();
+
+        let shape = ShapeHandle::new(Ball::new(6.0));
+        let collider_desc =
+            ColliderDesc::new(shape).material(MaterialHandle::new(BasicMaterial::new(0.0, 0.0))).translation(Vector2::new(0.0, -10.0));
+
+        let body = physics_world.insert_body(rigid_body, collider_desc);
+
+        let frame = sprite_frame(world, "shell");
+
+        world
+            .create_entity()
+            .with(Sprite {
+                texture: frame.texture,
+                src: frame.src,
+                width: 32f32,
+                height: 32f32,
+                repeat: Vector2::new(1, 1),
+            })
+            .with(Transform {
+                position,
+                rotation: 0.0,
+                scale: Vector2::new(1.0, 1.0),
+            })
+            .with(body)
+            .with(Carryable)
+            .build()
+    }
+}
+
+/// A koopa that can't be stomped on, only spin jumped on.
+pub struct Spiny;
+
+impl Spiny {
+    pub fn add(
+        world: &mut World,
+        position: Point2<f32>,
+        physics_world: &mut PhysicsWorld,
+    ) -> Entity {
+        let entity = KoopaTroopa::add(world, position, physics_world);
+
+        world
+            .write_storage::<Spiky>()
+            .insert(entity, Spiky)
+            .expect("Failed to make enemy spiky");
+
+        entity
+    }
+}
+
+pub struct CheckpointGate;
+
+impl CheckpointGate {
+    pub fn add(
+        world: &mut World,
+        position: Point2<f32>,
+        physics_world: &mut PhysicsWorld,
+    ) -> Entity {
+        let rigid_body = RigidBodyDesc::new()
+            .position(Isometry2::new(Vector2::new(position.x, position.y), 0.0))
+            .status(BodyStatus::Static)
+            .build();
+
+        let shape = ShapeHandle::new(Cuboid::new(Vector2::new(4.0, 16.0)));
+        let collider_desc = ColliderDesc::new(shape)
+            .translation(Vector2::new(0.0, 8.0))
+            .sensor(true)
+            .user_data("checkpoint");
+
+        let body = physics_world.insert_body(rigid_body, collider_desc);
+
+        let frame = sprite_frame(world, "pole");
+
+        world
+            .create_entity()
+            .with(Sprite {
+                texture: frame.texture,
+                src: frame.src,
+                width: 16f32,
+                height: 16f32,
+                repeat: Vector2::new(1, 1),
+            })
+            .with(Transform {
+                position,
+                rotation: 0.0,
+                scale: Vector2::new(1.0, 1.0),
+            })
+            .with(body)
+            .with(Checkpoint::default())
+            .build()
+    }
+}
+
+pub struct Flagpole;
+
+impl Flagpole {
+    pub fn add(
+        world: &mut World,
+        position: Point2<f32>,
+        height: f32,
+        castle_x: f32,
+        physics_world: &mut PhysicsWorld,
+    ) -> Entity {
+        let tile_size = 16f32;
+        // Sprites repeat downwards, so the pole is anchored at its top tile.
+        let top = Point2::new(position.x, position.y + height - tile_size / 2.0);
+
+        let rigid_body = RigidBodyDesc::new()
+            .position(Isometry2::new(Vector2::new(top.x, top.y), 0.0))
+            .status(BodyStatus::Static)
+            .build();
+
+        let shape = ShapeHandle::new(Cuboid::new(Vector2::new(2.0, height / 2.0)));
+        let collider_desc = ColliderDesc::new(shape)
+            .translation(Vector2::new(0.0, tile_size / 2.0 - height / 2.0))
+            .sensor(true)
+            .user_data("goal");
+
+        let body = physics_world.insert_body(rigid_body, collider_desc);
+
+        let frame = sprite_frame(world, "pole");
+
+        world
+            .create_entity()
+            .with(Sprite {
+                texture: frame.texture,
+                src: frame.src,
+                width: tile_size,
+                height: tile_size,
+                repeat: Vector2::new(1, (height / tile_size).ceil() as u32),
+            })
+            .with(Transform {
+                position: top,
+                rotation: 0.0,
+                scale: Vector2::new(1.0, 1.0),
+            })
+            .with(body)
+            .with(Goal {
+                bottom: position.y,
+                height,
+                castle_x,
+            })
+            .build()
+    }
+}
+
+pub struct WarpPipe;
+
+impl WarpPipe {
+    pub fn add(
+        world: &mut World,
+        position: Point2<f32>,
+        block_count: Vector2<u32>,
+        direction: PipeDirection,
+        destination: Option<WarpDestination>,
+        physics_world: &mut PhysicsWorld,
+    ) -> Entity {
+        let (width, height) = (16f32, 16f32);
+
+        let rigid_body = RigidBodyDesc::new()
+            .position(Isometry2::new(Vector2::new(position.x, position.y), 0.0))
+            .status(BodyStatus::Static)
+            .build();
+
+        let shape = ShapeHandle::new(Cuboid::new(Vector2::new(block_count.x as f32 * width / 2.0, block_count.y as f32 * height / 2.0)));
+        let collider_desc = ColliderDesc::new(shape)
+            .translation(Vector2::new((block_count.x as f32 * width) / 2.0 - width / 2.0, (block_count.y as f32 * -height) / 2.0 + height / 2.0))
+            .material(MaterialHandle::new(BasicMaterial::new(0.0, 0.2))).user_data("brick");
+
+        let body = physics_world.insert_body(rigid_body, collider_desc);
+
+        let frame = sprite_frame(world, "block");
+
+        world
+            .create_entity()
+            .with(Sprite {
+                texture: frame.texture,
+                src: frame.src,
+                width,
+                height,
+                repeat: block_count,
+            })
+            .with(Transform {
+                position,
+                rotation: 0.0,
+                scale: Vector2::new(1.0, 1.0),
+            })
+            .with(body)
+            .with(Pipe {
+                direction,
+                opening: Self::opening(position, block_count, direction),
+                destination,
+            })
+            .build()
+    }
+
+    /// Center of the side of the pipe the player enters through when travelling in `direction`.
+    pub fn opening(position: Point2<f32>, block_count: Vector2<u32>, direction: PipeDirection) -> Point2<f32> {
+        let (width, height) = (16f32, 16f32);
+        let left = position.x - width / 2.0;
+        let top = position.y + height / 2.0;
+        let right = left + block_count.x as f32 * width;
+        let bottom = top - block_count.y as f32 * height;
+
+        match direction {
+            PipeDirection::Down => Point2::new((left + right) / 2.0, top),
+            PipeDirection::Up => Point2::new((left + right) / 2.0, bottom),
+            PipeDirection::Right => Point2::new(left, (top + bottom) / 2.0),
+            PipeDirection::Left => Point2::new(right, (top + bottom) / 2.0),
+        }
+    }
+}
+
+pub struct Platform;
+
+impl Platform {
+    pub fn add(
+        world: &mut World,
+        position: Point2<f32>,
+        block_count: u32,
+        path: PlatformPath,
+        physics_world: &mut PhysicsWorld,
+    ) -> Entity {
+        let (width, height) = (16f32, 16f32);
+
+        let rigid_body = RigidBodyDesc::new()
+            .position(Isometry2::new(Vector2::new(position.x, position.y), 0.0))
+            .status(BodyStatus::Kinematic)
+            .build();
+
+        let shape = ShapeHandle::new(Cuboid::new(Vector2::new(block_count as f32 * width / 2.0, height / 2.0)));
+        let collider_desc = ColliderDesc::new(shape)
+            .translation(Vector2::new((block_count as f32 * width) / 2.0 - width / 2.0, 0.0))
+            .material(MaterialHandle::new(BasicMaterial::new(0.0, 0.2))).user_data("platform");
+
+        let body = physics_world.insert_body(rigid_body, collider_desc);
+
+        let frame = sprite_frame(world, "block");
+
+        world
+            .create_entity()
+            .with(Sprite {
+                texture: frame.texture,
+                src: frame.src,
+                width,
+                height,
+                repeat: Vector2::new(block_count, 1),
+            })
+            .with(Transform {
+                position,
+                rotation: 0.0,
+                scale: Vector2::new(1.0, 1.0),
+            })
+            .with(body)
+            .with(MovingPlatform {
+                path,
+                origin: position,
+                ticks: 0,
+                triggered: false,
+            })
+            .build()
+    }
+}
+
+pub struct Slope;
+
+impl Slope {
+    /// Adds a slope spanning `run` tiles horizontally, with `position` at its bottom corner.
+    /// `run` can't be 0, which levels are checked for when they load.
+    pub fn add(
+        world: &mut World,
+        position: Point2<f32>,
+        run: u32,
+        angle: SlopeAngle,
+        direction: SlopeDirection,
+        physics_world: &mut PhysicsWorld,
+    ) -> Entity {
+        let (width, height) = (16f32, 16f32);
+        let run_length = run as f32 * width;
+        let rise = run_length * angle.radians().tan();
+        let surface_length = (run_length * run_length + rise * rise).sqrt();
+
+        // The body lies along the slope surface so the sprite tiles can follow it.
+        let (start, rotation) = match direction {
+            SlopeDirection::Up => (Vector2::new(position.x, position.y), angle.radians()),
+            SlopeDirection::Down => (Vector2::new(position.x, position.y + rise), -angle.radians()),
+        };
+        let tile_offset = Vector2::new(width / 2.0, -height / 2.0);
+        let iso = Isometry2::new(start, rotation) * Isometry2::new(tile_offset, 0.0);
+
+        let corner = match direction {
+            SlopeDirection::Up => Vector2::new(run_length, 0.0),
+            SlopeDirection::Down => Vector2::new(0.0, 0.0),
+        };
+        let bottom = match direction {
+            SlopeDirection::Up => Vector2::new(0.0, 0.0),
+            SlopeDirection::Down => Vector2::new(run_length, 0.0),
+        };
+        let top = match direction {
+            SlopeDirection::Up => Vector2::new(run_length, rise),
+            SlopeDirection::Down => Vector2::new(0.0, rise),
+        };
+
+        let to_local = |point: Vector2<f32>| {
+            iso.inverse_transform_point(&PhysicsPoint2::new(position.x + point.x, position.y + point.y))
+        };
+        let points = [to_local(bottom), to_local(corner), to_local(top)];
+
+        let shape = ShapeHandle::new(ConvexPolygon::try_from_points(&points).expect("Slope spanning no tiles"));
+        let rigid_body = RigidBodyDesc::new()
+            .position(iso)
+            .status(BodyStatus::Static)
+            .build();
+
+        let collider_desc = ColliderDesc::new(shape)
+            .material(MaterialHandle::new(BasicMaterial::new(0.0, 0.2)))
+            .user_data("slope");
+
+        let body = physics_world.insert_body(rigid_body, collider_desc);
+
+        let frame = sprite_frame(world, "block");
+
+        world
+            .create_entity()
+            .with(Sprite {
+                texture: frame.texture,
+                src: frame.src,
+                width,
+                height,
+                repeat: Vector2::new((surface_length / width).ceil() as u32, 1),
+            })
+            .with(Transform {
+                position: Point2::new(iso.translation.x, iso.translation.y),
+                rotation,
+                scale: Vector2::new(1.0, 1.0),
+            })
+            .with(body)
+            .build()
+    }
+}
+
+/// Looks up a frame of the texture atlas, which the entity builders can't do without.
+fn sprite_frame(world: &World, name: &str) -> SpriteFrame {
+    world
+        .read_resource::<TextureIndex>()
+        .frame(name)
+        .unwrap_or_else(|| panic!("Missing sprite frame {}, frames named in code go in SPRITE_FRAMES", name))
+}
+
+/// Starts the animation graph of the animation set, with the clips its states play.
+fn build_animation(world: &World, set: &str) -> Animation {
+    let graph = world
+        .read_resource::<AnimationGraphs>()
+        .get(set)
+        .unwrap_or_else(|| panic!("Missing animation graph {}", set));
+
+    let textures = world.read_resource::<TextureIndex>();
+    let animations = graph
+        .states
+        .iter()
+        .filter_map(|(state, state_data)| {
+            let clip = textures.clip(set, state_data.clip.as_deref().unwrap_or(state))?;
+            Some((state.clone(), AnimationParams::new(clip.clone())))
+        })
+        .collect();
+
+    Animation {
+        current_state: graph.initial.clone(),
+        playing: None,
+        parameters: Default::default(),
+        animations,
+        speed_factor: world.read_resource::<Tuning>().animation.speed_factor,
+        graph,
+    }
+}
@@ -1,4 +1,4 @@
-use engine::game::SuperMario;
+use engine::game::{SuperMario, WINDOW_TITLE};
 use ggez::{
     conf::{WindowMode, WindowSetup},
     event, ContextBuilder,
@@ -23,7 +23,7 @@ fn main() {
     };
 
     let (mut ctx, mut event_loop) = ContextBuilder::new("super_mario", "Michael Iskandarani")
-        .window_setup(WindowSetup::default().title("Super Mario"))
+        .window_setup(WindowSetup::default().title(WINDOW_TITLE))
         .window_mode(WindowMode::default().dimensions(1280f32, 720f32))
         .add_resource_path(resource_dir)
         .build()
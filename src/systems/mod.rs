@@ -1,4 +1,5 @@
 use std::f32::consts::PI;
+use std::sync::Arc;
 
 use ggez::{
     Context,
@@ -9,21 +10,76 @@ use ggez::{
 };
 use ggez::input::keyboard::KeyMods;
 use nphysics2d::algebra::{Force2, ForceType, Velocity2};
-use nphysics2d::material::BasicMaterial;
 use nphysics2d::nalgebra::Isometry2;
-use nphysics2d::ncollide2d::query::Proximity;
+use nphysics2d::ncollide2d::query::{self, Proximity};
+use nphysics2d::object::ColliderDesc;
+use nphysics2d::object::{Body as _, BodyStatus};
 use specs::{Read, ReadStorage, System, world::Index, Write, WriteStorage};
 use specs::hibitset::BitSetLike;
 use specs::prelude::*;
 
-use crate::components::{Animation, AnimationStates, Jumper, Sprite, BasicAI};
-use crate::components::{Body, CameraTarget, FeetSensor, Player, Transform};
+use crate::components::{Animation, Jumper, Sprite, BasicAI, Killed, DeathSequence, Checkpoint};
+use crate::components::{Goal, GoalPhase, LevelComplete, Pipe, PipeDirection, PipeTransit};
+use crate::components::{Breakable, Crouch, DropThrough, MovingPlatform, PlatformPath, SpecialMove, Spiky};
+use crate::components::{Carrier, Carryable, Dust, InWater, PreviousTransform, RenderLayer, SwimAI};
+use crate::components::{Body, CameraTarget, CharacterController, FeetSensor, GravityScale, Player, Transform};
 use crate::engine::{
+    assets::TextureIndex,
     camera::Camera,
-    game::TARGET_FPS,
-    physics::PhysicsWorld,
-    resources::{DebugRenderables, DeltaTime, InputEvents, Renderables},
+    game::{interpolation_alpha, TARGET_FPS},
+    physics::{PhysicsWorld, VolumeEvent},
+    resources::{
+        AnimationEvent, AnimationEvents, CheckpointState, DebugRenderables, DeltaTime, GameSession, InputEvents, LevelBounds,
+        LevelInfo, LevelTimer, PendingTransition, Renderables, SceneTransition, TimerState,
+    },
+    tuning::{MovementProfile, Tuning},
 };
+use crate::util::lerp::Lerp;
+
+/// Ticks the player stays frozen in place after being killed.
+pub const DEATH_FREEZE_TICKS: u32 = 30;
+/// Ticks from being killed until the level is reloaded.
+pub const DEATH_SEQUENCE_TICKS: u32 = 180;
+const DEATH_HOP_VELOCITY: f32 = 250.0;
+
+/// Points for grabbing the flagpole, by the fraction of the pole height grabbed at.
+const FLAGPOLE_SCORES: [(f32, u32); 5] = [(0.9, 5000), (0.7, 2000), (0.5, 800), (0.3, 400), (0.0, 100)];
+const CASTLE_WALK_SPEED: f32 = 60.0;
+/// Zoom the camera closes in to while the player walks off to the castle.
+const GOAL_ZOOM: f32 = 5.0;
+/// Seconds of leftover time converted into score on every tick of the tally.
+const TALLY_SECONDS_PER_TICK: u32 = 1;
+
+/// Ticks spent sliding into a pipe before warping to its destination.
+pub const PIPE_ENTER_TICKS: u32 = 40;
+const PIPE_ENTER_SPEED: f32 = 32.0;
+/// How far off the center of a pipe mouth the player can be and still enter it.
+const PIPE_ENTER_TOLERANCE: f32 = 12.0;
+
+/// How far apart a body and the ground under its feet can be for the ground normal to be found.
+const GROUND_CONTACT_PREDICTION: f32 = 4.0;
+/// How far to the side the player feels for a wall to slide down.
+const WALL_PROBE_DISTANCE: f32 = 1.0;
+
+/// Ticks a player keeps ignoring one-way platforms after pressing down and jump on one.
+const DROP_THROUGH_TICKS: u32 = 12;
+
+const PLATFORM_FALL_ACCELERATION: f32 = 450.0;
+const PLATFORM_MAX_FALL_SPEED: f32 = 240.0;
+/// Pixels the view shakes by when a ground pound hits the ground, and how fast that dies down.
+const GROUND_POUND_SHAKE: f32 = 3.0;
+const GROUND_POUND_SHAKE_HALF_LIFE: f32 = 0.07;
+/// How far in front of the player a carried object is held.
+const CARRY_DISTANCE: f32 = 14.0;
+/// Radians per tick of the up and down bob of swimming enemies.
+const SWIM_BOB_RATE: f32 = 0.05;
+/// How far below the middle of a character its feet are, where footsteps kick up dust.
+const FOOTSTEP_DROP: f32 = 8.0;
+/// Ticks a puff of dust lasts, starting out at `DUST_SCALE` of its sprite and shrinking away.
+const DUST_TICKS: u32 = 12;
+const DUST_SCALE: f32 = 0.375;
+/// Pixels per tick a puff of dust drifts up by.
+const DUST_RISE: f32 = 0.25;
 
 pub struct RenderingSystem<'a> {
     ctx: &'a mut Context,
@@ -39,6 +95,8 @@ impl<'a> System<'a> for RenderingSystem<'a> {
     type SystemData = (
         ReadStorage<'a, Sprite>,
         ReadStorage<'a, Transform>,
+        ReadStorage<'a, PreviousTransform>,
+        ReadStorage<'a, RenderLayer>,
         Write<'a, Renderables>,
         Write<'a, DebugRenderables>,
         Read<'a, Camera>,
@@ -49,35 +107,89 @@ impl<'a> System<'a> for RenderingSystem<'a> {
         (
             sprite_storage,
             transform_storage,
+            previous_transform_storage,
+            render_layer_storage,
             mut renderables,
             mut debug_renderables,
             camera,
         ): Self::SystemData,
     ) {
         let (width, height) = graphics::size(self.ctx);
+        let alpha = interpolation_alpha(self.ctx);
+        let view = camera.view_position(alpha);
+        let zoom = camera.view_zoom(alpha);
+
+        for (sprite, transform, previous, render_layer) in (
+            &sprite_storage,
+            &transform_storage,
+            (&previous_transform_storage).maybe(),
+            (&render_layer_storage).maybe(),
+        )
+            .join()
+        {
+            let render_layer = render_layer.copied().unwrap_or_default();
+            let layer = renderables.0.entry((render_layer.depth, sprite.texture)).or_default();
+
+            // Drawn part of the way from where it was last tick, as draws don't line up with ticks.
+            let (position, rotation) = match previous {
+                Some(previous) => (
+                    Point2::from(previous.position.coords.lerp_bounded(transform.position.coords, alpha)),
+                    previous.rotation.lerp_bounded(transform.rotation, alpha),
+                ),
+                None => (transform.position, transform.rotation),
+            };
+            let (sin, cos) = rotation.sin_cos();
 
-        for (sprite, transform) in (&sprite_storage, &transform_storage).join() {
             for count_x in 0..sprite.repeat.x {
                 for count_y in 0..sprite.repeat.y {
-                    let pos_x = transform.position.x + count_x as f32 * sprite.width;
-                    let pos_y = -transform.position.y + count_y as f32 * sprite.height;
+                    // Repeated tiles follow the rotation of the sprite, e.g. along a slope.
+                    let offset_x = count_x as f32 * sprite.width;
+                    let offset_y = -(count_y as f32) * sprite.height;
+                    let pos_x = position.x + offset_x * cos - offset_y * sin;
+                    let pos_y = -(position.y + offset_x * sin + offset_y * cos);
                     let draw_param = graphics::DrawParam::new()
                         .src(sprite.src)
                         .scale(Vector2::new(transform.scale.x, transform.scale.y))
                         .dest(Point2::new(
-                            (pos_x - camera.position.x) + width / (2.0 * camera.zoom),
-                            (pos_y + camera.position.y) + height / (2.0 * camera.zoom),
+                            (pos_x - view.x * render_layer.parallax) + width / (2.0 * zoom),
+                            (pos_y + view.y * render_layer.parallax) + height / (2.0 * zoom),
                         ))
                         .offset(Point2::new(0.5, 0.5))
-                        .rotation(transform.rotation);
+                        .rotation(-rotation);
 
-                    renderables.0.push_back(draw_param);
+                    layer.push_back(draw_param);
                 }
             }
         }
     }
 }
 
+/// Keeps where every entity was at the end of the previous tick, for render interpolation.
+/// Runs before the tick's systems move anything.
+pub struct TransformHistorySystem;
+
+impl<'a> System<'a> for TransformHistorySystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Transform>,
+        WriteStorage<'a, PreviousTransform>,
+    );
+
+    fn run(&mut self, (entities, transform_storage, mut previous_transform_storage): Self::SystemData) {
+        for (entity, transform) in (&entities, &transform_storage).join() {
+            previous_transform_storage
+                .insert(
+                    entity,
+                    PreviousTransform {
+                        position: transform.position,
+                        rotation: transform.rotation,
+                    },
+                )
+                .expect("Failed to keep previous transform");
+        }
+    }
+}
+
 pub struct PlayerControlSystem;
 
 impl<'a> System<'a> for PlayerControlSystem {
@@ -85,11 +197,22 @@ impl<'a> System<'a> for PlayerControlSystem {
         WriteStorage<'a, Transform>,
         Read<'a, InputEvents>,
         ReadStorage<'a, Player>,
-        ReadStorage<'a, Body>,
-        Write<'a, PhysicsWorld>,
+        WriteStorage<'a, Body>,
+        WriteStorage<'a, CharacterController>,
         ReadStorage<'a, FeetSensor>,
         WriteStorage<'a, Jumper>,
         WriteStorage<'a, Animation>,
+        ReadStorage<'a, Killed>,
+        ReadStorage<'a, LevelComplete>,
+        ReadStorage<'a, PipeTransit>,
+        Entities<'a>,
+        WriteStorage<'a, DropThrough>,
+        WriteStorage<'a, GravityScale>,
+        WriteStorage<'a, SpecialMove>,
+        WriteStorage<'a, Crouch>,
+        ReadStorage<'a, InWater>,
+        Write<'a, PhysicsWorld>,
+        Read<'a, Tuning>,
     );
 
     fn run(
@@ -98,110 +221,432 @@ impl<'a> System<'a> for PlayerControlSystem {
             mut transform_storage,
             input_events,
             player,
-            body_storage,
-            mut physics_world,
+            mut body_storage,
+            mut controller_storage,
             feet_sensor_storage,
             mut jumper_storage,
             mut animation_storage,
+            killed_storage,
+            level_complete_storage,
+            pipe_transit_storage,
+            entities,
+            mut drop_through_storage,
+            mut gravity_scale_storage,
+            mut special_move_storage,
+            mut crouch_storage,
+            in_water_storage,
+            mut physics_world,
+            tuning,
         ): Self::SystemData,
     ) {
-        let center_point = nphysics2d::nalgebra::Point2::new(0.0, 0.0);
-        let accel = (400.0 * 1.0 / TARGET_FPS as f32) as f32;
-        let mut force = Force2::linear(nphysics2d::nalgebra::Vector2::new(0.0, 0.0));
-        let mut top_speed = 100.0;
-        let mut friction = 0.5;
+        let movement = &tuning.movement;
+        let running = input_events.is_mod_active(KeyMods::SHIFT);
+        let up_pressed = input_events.is_key_pressed(&KeyCode::W) || input_events.is_key_pressed(&KeyCode::Up);
+        let down_pressed = input_events.is_key_pressed(&KeyCode::S) || input_events.is_key_pressed(&KeyCode::Down);
+        let spin_pressed = input_events.is_key_pressed(&KeyCode::X);
+        let jump_pressed = input_events.is_key_pressed(&KeyCode::Space) || spin_pressed;
+        let mut top_speed = movement.walk_speed;
 
-        if input_events.is_mod_active(KeyMods::SHIFT) {
-            top_speed = 240.0;
+        if running {
+            top_speed = movement.run_speed;
         }
 
-        for (transform, body, _, feet_sensor, jumper, animation) in (
+        for (entity, transform, body, controller, _, feet_sensor, mut jumper, animation, gravity_scale, special_move, crouch, in_water, _, _, _) in (
+            &entities,
             &mut transform_storage,
-            &body_storage,
+            &mut body_storage,
+            &mut controller_storage,
             &player,
-            (&feet_sensor_storage).maybe(),
+            &feet_sensor_storage,
             (&mut jumper_storage).maybe(),
             (&mut animation_storage).maybe(),
+            (&mut gravity_scale_storage).maybe(),
+            (&mut special_move_storage).maybe(),
+            (&mut crouch_storage).maybe(),
+            (&in_water_storage).maybe(),
+            !&killed_storage,
+            !&level_complete_storage,
+            !&pipe_transit_storage,
         )
             .join()
         {
-            let rigid_body = physics_world.bodies.get(body.rigid_body_handle);
+            let mut special = special_move.as_ref().map_or(SpecialMove::None, |special_move| **special_move);
+            let mut direction = 0.0;
 
-            if let Some(rigid_body) = rigid_body {
-                let current_velocity = rigid_body.velocity_at_point(0, &center_point);
+            if input_events.is_key_pressed(&KeyCode::D) || input_events.is_key_pressed(&KeyCode::Right) {
+                direction = 1.0;
+            } else if input_events.is_key_pressed(&KeyCode::A) || input_events.is_key_pressed(&KeyCode::Left) {
+                direction = -1.0;
+            }
 
-                if let Some(feet_sensor) = feet_sensor {
-                    if input_events.is_key_pressed(&KeyCode::D)
-                        || input_events.is_key_pressed(&KeyCode::Right)
-                    {
-                        if current_velocity.linear.x < top_speed {
-                            force.linear.x = accel;
-                        }
+            let jumping = jumper.as_ref().map_or(false, |jumper| jumper.jumping);
 
-                        transform.scale.x = transform.scale.x.abs();
-                        friction = 0.0;
-                    } else if input_events.is_key_pressed(&KeyCode::A)
-                        || input_events.is_key_pressed(&KeyCode::Left)
-                    {
-                        if current_velocity.linear.x > -top_speed {
-                            force.linear.x = -accel;
-                        }
+            let swimming = in_water.is_some();
+
+            if ((feet_sensor.on_floor && !jumping) || swimming) && special != SpecialMove::Climb {
+                special = SpecialMove::None;
+            }
+
+            let on_climbable = physics_world
+                .volumes_containing(body.collider_handle)
+                .any(|volume| physics_world.collider_tag(volume) == Some("climbable"));
+
+            if special == SpecialMove::Climb {
+                // Lets go when climbing off the vine, or down onto the ground.
+                if !on_climbable || (feet_sensor.on_floor && !up_pressed) {
+                    special = SpecialMove::None;
+                }
+            } else if on_climbable && up_pressed && controller.velocity.y <= 0.0 {
+                // Not on the way up, or jumping off a vine would grab right back onto it.
+                special = SpecialMove::Climb;
+            }
+
+            let climbing = special == SpecialMove::Climb;
+
+            let ground_pound = if let SpecialMove::GroundPound { .. } = special { true } else { false };
+
+            if !feet_sensor.on_floor && !ground_pound && !swimming && !climbing && controller.velocity.y < 0.0 && direction != 0.0 {
+                // Feel for a wall in the direction pushed in.
+                let probe = physics_world.move_character(
+                    body.collider_handle,
+                    nphysics2d::nalgebra::Vector2::new(direction * WALL_PROBE_DISTANCE, 0.0),
+                    false,
+                    0.0,
+                );
+
+                special = match probe.wall {
+                    Some(normal) if normal.x * direction < 0.0 => SpecialMove::WallSlide { normal_x: normal.x },
+                    _ => match special {
+                        SpecialMove::WallSlide { .. } => SpecialMove::None,
+                        special => special,
+                    },
+                };
+            } else if let SpecialMove::WallSlide { .. } = special {
+                special = SpecialMove::None;
+            }
+
+            // Pressing jump while pressing down drops through a platform, holding down on its own crouches.
+            let jump_just_pressed = jump_pressed && jumper.as_ref().map_or(false, |jumper| !jumper.held);
+            let was_crouching = crouch.as_ref().map_or(false, |crouch| crouch.crouching);
+            // Only worth it over a one-way platform, elsewhere the press is an ordinary jump.
+            let dropping = feet_sensor.on_floor
+                && down_pressed
+                && jump_just_pressed
+                && !was_crouching
+                && !climbing
+                && physics_world.on_one_way(feet_sensor.collider_handle);
+            // Down has to be pressed again in the air to ground pound, so holding it on from a
+            // drop or a crouch doesn't slam the player right back down.
+            let down_just_pressed = down_pressed && jumper.as_ref().map_or(true, |jumper| !jumper.down_held);
 
-                        transform.scale.x = -transform.scale.x.abs();
-                        friction = 0.0;
+            let mut crouching = false;
+
+            if let Some(crouch) = crouch {
+                if down_pressed && feet_sensor.on_floor && !crouch.crouching && !climbing && !dropping {
+                    crouch.crouching = true;
+                    physics_world.swap_collider(
+                        body,
+                        ColliderDesc::new(crouch.crouching_shape.clone()).translation(crouch.crouching_offset),
+                    );
+                } else if !down_pressed && crouch.crouching {
+                    // Stays down until there's room to stand up.
+                    let probe = physics_world.move_character(
+                        body.collider_handle,
+                        nphysics2d::nalgebra::Vector2::new(0.0, crouch.clearance()),
+                        false,
+                        0.0,
+                    );
+
+                    if !probe.ceiling {
+                        crouch.crouching = false;
+                        physics_world.swap_collider(body, ColliderDesc::new(crouch.standing_shape.clone()));
                     }
+                }
 
-                    if let Some(jumper) = jumper {
-                        if feet_sensor.on_floor {
-                            jumper.jump_active = true;
-                        }
+                crouching = crouch.crouching;
+            }
 
-                        if input_events.is_key_pressed(&KeyCode::Space) {
-                            if jumper.jump_active {
-                                let hold_time = input_events
-                                    .key_hold_time(&KeyCode::Space)
-                                    .unwrap()
-                                    .elapsed()
-                                    .as_secs_f32();
+            if !feet_sensor.on_floor && down_just_pressed && !ground_pound && !crouching && !swimming && !climbing {
+                special = SpecialMove::GroundPound { ticks: 0 };
+            }
 
-                                let mut jump_time = 0.2;
+            let crouch_sliding = crouching && feet_sensor.on_floor;
 
-                                if input_events.is_mod_active(KeyMods::SHIFT) {
-                                    jump_time = 0.24;
-                                }
+            if direction != 0.0 && !ground_pound && !crouch_sliding {
+                transform.scale.x = direction * transform.scale.x.abs();
+            }
 
-                                if hold_time < jump_time {
-                                    if let Some(animation) = animation {
-                                        animation.current_state = AnimationStates::Jumping;
-                                    }
+            if climbing {
+                let vertical = if up_pressed { 1.0 } else if down_pressed { -1.0 } else { 0.0 };
+                controller.velocity = nphysics2d::nalgebra::Vector2::new(direction, vertical) * movement.climb_speed;
+            } else if let SpecialMove::GroundPound { .. } = special {
+                controller.velocity.x = 0.0;
+            } else if crouch_sliding {
+                let step = movement.crouch_slide_deceleration / TARGET_FPS as f32;
+                controller.velocity.x = approach(controller.velocity.x, 0.0, step);
+            } else {
+                let top_speed = if swimming { movement.swim_speed } else { top_speed };
+                controller.velocity.x =
+                    approach_speed(movement, controller.velocity.x, direction, top_speed, feet_sensor.on_floor);
+            }
 
-                                    force.linear.y = 40.0 * ((jump_time - hold_time) / jump_time);
-                                    friction = 0.0;
-                                }
-                            }
-                        } else {
-                            jumper.jump_active = false;
-                        }
+            if dropping {
+                drop_through_storage
+                    .insert(entity, DropThrough { ticks: DROP_THROUGH_TICKS })
+                    .expect("Failed to drop through platform");
+            }
+
+            if let Some(ref mut jumper) = jumper {
+                if feet_sensor.on_floor && !jumper.jumping {
+                    jumper.coyote_ticks = movement.coyote_ticks;
+                } else {
+                    jumper.coyote_ticks = jumper.coyote_ticks.saturating_sub(1);
+                }
+
+                if jump_pressed && !jumper.held && !dropping {
+                    jumper.buffer_ticks = movement.jump_buffer_ticks;
+                } else {
+                    jumper.buffer_ticks = jumper.buffer_ticks.saturating_sub(1);
+                }
+
+                jumper.held = jump_pressed;
+                jumper.down_held = down_pressed;
+
+                if swimming && jumper.buffer_ticks > 0 {
+                    // Every press is a swim stroke, with or without ground underneath.
+                    jumper.buffer_ticks = 0;
+                    jumper.jump_active = false;
+                    jumper.jumping = true;
+                    controller.velocity.y = movement.swim_stroke_velocity;
+                } else if climbing && jumper.buffer_ticks > 0 {
+                    jumper.buffer_ticks = 0;
+                    jumper.jump_active = true;
+                    jumper.jumping = true;
+                    controller.velocity.y = movement.jump_velocity;
+                    special = SpecialMove::None;
+                } else if let (true, SpecialMove::WallSlide { normal_x }) = (jumper.buffer_ticks > 0, special) {
+                    jumper.buffer_ticks = 0;
+                    jumper.jump_active = true;
+                    jumper.jumping = true;
+
+                    controller.velocity = nphysics2d::nalgebra::Vector2::new(
+                        normal_x.signum() * movement.wall_jump_push,
+                        movement.wall_jump_velocity,
+                    );
+                    transform.scale.x = normal_x.signum() * transform.scale.x.abs();
+                    special = SpecialMove::None;
+                } else if jumper.buffer_ticks > 0 && jumper.coyote_ticks > 0 {
+                    jumper.buffer_ticks = 0;
+                    jumper.coyote_ticks = 0;
+                    jumper.jump_active = true;
+                    jumper.jumping = true;
+
+                    if spin_pressed {
+                        controller.velocity.y = movement.spin_jump_velocity;
+                        special = SpecialMove::SpinJump;
+                    } else {
+                        controller.velocity.y = if running { movement.run_jump_velocity } else { movement.jump_velocity };
+                    }
+                }
+
+                if jumper.jump_active {
+                    if !jump_pressed {
+                        // Letting go early cuts the jump short.
+                        controller.velocity.y = controller.velocity.y.min(movement.jump_cut_velocity);
+                        jumper.jump_active = false;
+                    } else if controller.velocity.y <= 0.0 {
+                        jumper.jump_active = false;
+                    }
+                }
+            }
+
+            let jumping = jumper.as_ref().map_or(false, |jumper| jumper.jumping);
+            let mut scale = if swimming {
+                tuning.physics.water_gravity_scale
+            } else if controller.velocity.y < -movement.apex_speed {
+                movement.fall_gravity_scale
+            } else if jump_pressed && jumping && controller.velocity.y.abs() < movement.apex_speed {
+                movement.apex_gravity_scale
+            } else {
+                1.0
+            };
+
+            match special {
+                SpecialMove::GroundPound { ref mut ticks } => {
+                    // Hangs in the air for a moment before slamming down.
+                    *ticks += 1;
+                    scale = 0.0;
+
+                    if *ticks <= movement.ground_pound_hang_ticks {
+                        controller.velocity.y = 0.0;
+                    } else {
+                        controller.velocity.y = -movement.ground_pound_speed;
                     }
                 }
+                SpecialMove::WallSlide { .. } => {
+                    controller.velocity.y = controller.velocity.y.max(-movement.wall_slide_speed);
+                }
+                SpecialMove::Climb => scale = 0.0,
+                SpecialMove::SpinJump | SpecialMove::None => {}
+            }
+
+            if let Some(gravity_scale) = gravity_scale {
+                gravity_scale.0 = scale;
+            }
+
+            if let Some(animation) = animation {
+                let parameters = &mut animation.parameters;
+                parameters.set_bool("spin_jump", matches!(special, SpecialMove::SpinJump));
+                parameters.set_bool("ground_pound", matches!(special, SpecialMove::GroundPound { .. }));
+                parameters.set_bool("wall_slide", matches!(special, SpecialMove::WallSlide { .. }));
+                parameters.set_bool("climbing", matches!(special, SpecialMove::Climb));
+                parameters.set_bool("crouching", crouching);
+            }
+
+            if let Some(special_move) = special_move {
+                *special_move = special;
+            }
+        }
+    }
+}
+
+/// Moves a horizontal speed one tick closer to what the input asks for. Turning around on the
+/// ground skids, and letting go of run slows down gradually rather than at once.
+fn approach_speed(movement: &MovementProfile, speed: f32, direction: f32, top_speed: f32, on_floor: bool) -> f32 {
+    let (target, rate) = if direction == 0.0 {
+        (0.0, if on_floor { movement.ground_deceleration } else { movement.air_deceleration })
+    } else if on_floor && speed * direction < 0.0 {
+        (direction * top_speed, movement.skid_deceleration)
+    } else if speed * direction > top_speed {
+        (direction * top_speed, movement.ground_deceleration)
+    } else {
+        (direction * top_speed, if on_floor { movement.ground_acceleration } else { movement.air_acceleration })
+    };
+
+    approach(speed, target, rate / TARGET_FPS as f32)
+}
+
+/// Moves `value` towards `target` by at most `step`.
+fn approach(value: f32, target: f32, step: f32) -> f32 {
+    if value < target {
+        (value + step).min(target)
+    } else {
+        (value - step).max(target)
+    }
+}
+
+pub struct CharacterControllerSystem;
+
+impl<'a> System<'a> for CharacterControllerSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Body>,
+        WriteStorage<'a, CharacterController>,
+        WriteStorage<'a, FeetSensor>,
+        WriteStorage<'a, Jumper>,
+        ReadStorage<'a, DropThrough>,
+        ReadStorage<'a, GravityScale>,
+        ReadStorage<'a, InWater>,
+        Write<'a, PhysicsWorld>,
+        Read<'a, Tuning>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            body_storage,
+            mut controller_storage,
+            mut feet_sensor_storage,
+            mut jumper_storage,
+            drop_through_storage,
+            gravity_scale_storage,
+            in_water_storage,
+            mut physics_world,
+            tuning,
+        ): Self::SystemData,
+    ) {
+        let delta = 1.0 / TARGET_FPS as f32;
+        let gravity = physics_world.gravity();
+
+        for (entity, body, controller, feet_sensor, jumper, gravity_scale, in_water) in (
+            &entities,
+            &body_storage,
+            &mut controller_storage,
+            &mut feet_sensor_storage,
+            (&mut jumper_storage).maybe(),
+            (&gravity_scale_storage).maybe(),
+            (&in_water_storage).maybe(),
+        )
+            .join()
+        {
+            if controller.gravity {
+                let scale = gravity_scale.map_or(1.0, |gravity_scale| gravity_scale.0);
+                controller.velocity += gravity * scale * delta;
+
+                if in_water.is_some() {
+                    controller.velocity /= 1.0 + tuning.physics.water_drag * delta;
+                    controller.velocity.y = controller.velocity.y.max(-tuning.physics.water_sink_speed);
+                } else {
+                    controller.velocity.y = controller.velocity.y.max(-tuning.physics.terminal_fall_speed);
+                }
             }
 
-            if force.linear.magnitude() > 0.0 {
-                let rigid_body = physics_world.bodies.get_mut(body.rigid_body_handle);
-                if let Some(rigid_body) = rigid_body {
-                    rigid_body.apply_force(0, &force, ForceType::Impulse, true);
+            let jumping = jumper.as_ref().map_or(false, |jumper| jumper.jumping);
+            let snap_distance = if feet_sensor.on_floor && !jumping && controller.velocity.y <= 0.0 {
+                tuning.movement.ground_snap_distance
+            } else {
+                0.0
+            };
+
+            let movement = physics_world.move_character(
+                body.collider_handle,
+                (controller.velocity + feet_sensor.ground_velocity) * delta,
+                drop_through_storage.contains(entity),
+                snap_distance,
+            );
+
+            if let Some(normal) = movement.wall {
+                if controller.velocity.x * normal.x < 0.0 {
+                    controller.velocity.x = 0.0;
                 }
             }
 
-            let collider = physics_world.colliders.get_mut(body.collider_handle);
+            if movement.ceiling && controller.velocity.y > 0.0 {
+                controller.velocity.y = 0.0;
+            }
+
+            match movement.ground {
+                Some((ground_handle, normal)) => {
+                    controller.velocity.y = controller.velocity.y.max(0.0);
 
-            if let Some(collider) = collider {
-                let material = collider.material_mut().downcast_mut::<BasicMaterial<f32>>();
+                    feet_sensor.on_floor = true;
+                    feet_sensor.ground_normal = normal;
+                    feet_sensor.ground_velocity = physics_world
+                        .bodies
+                        .rigid_body(ground_handle)
+                        .map_or(nphysics2d::nalgebra::Vector2::zeros(), |ground_body| ground_body.velocity().linear);
+
+                    if let Some(jumper) = jumper {
+                        if controller.velocity.y <= 0.0 {
+                            jumper.jumping = false;
+                        }
+                    }
+                }
+                None => {
+                    // Leaving a moving platform keeps its momentum.
+                    controller.velocity += feet_sensor.ground_velocity;
 
-                if let Some(material) = material {
-                    material.friction = friction;
+                    feet_sensor.on_floor = false;
+                    feet_sensor.ground_normal = nphysics2d::nalgebra::Vector2::y();
+                    feet_sensor.ground_velocity = nphysics2d::nalgebra::Vector2::zeros();
                 }
             }
+
+            // The physics world moves the body during its step, pushing dynamic bodies out of the way.
+            if let Some(rigid_body) = physics_world.bodies.rigid_body_mut(body.rigid_body_handle) {
+                rigid_body.set_linear_velocity(movement.translation / delta);
+            }
         }
     }
 }
@@ -215,6 +660,14 @@ impl<'a> System<'a> for PhysicsSystem {
         Write<'a, PhysicsWorld>,
         WriteStorage<'a, Animation>,
         WriteStorage<'a, FeetSensor>,
+        Entities<'a>,
+        WriteStorage<'a, DropThrough>,
+        WriteStorage<'a, Jumper>,
+        ReadStorage<'a, Killed>,
+        ReadStorage<'a, CharacterController>,
+        ReadStorage<'a, GravityScale>,
+        ReadStorage<'a, InWater>,
+        Read<'a, Tuning>,
     );
 
     fn run(
@@ -225,19 +678,115 @@ impl<'a> System<'a> for PhysicsSystem {
             mut physics_world,
             mut animation_storage,
             mut feet_sensor_storage,
+            entities,
+            mut drop_through_storage,
+            mut jumper_storage,
+            killed_storage,
+            controller_storage,
+            gravity_scale_storage,
+            in_water_storage,
+            tuning,
         ): Self::SystemData,
     ) {
+        for (entity, body) in (&entities, &body_storage).join() {
+            let dynamic = physics_world
+                .bodies
+                .rigid_body(body.rigid_body_handle)
+                .map_or(false, |rigid_body| rigid_body.status() == BodyStatus::Dynamic);
+
+            if dynamic {
+                physics_world.filter_one_way_contacts(body.collider_handle, drop_through_storage.contains(entity));
+            }
+        }
+
+        let mut dropped = Vec::new();
+
+        for (entity, drop_through) in (&entities, &mut drop_through_storage).join() {
+            drop_through.ticks = drop_through.ticks.saturating_sub(1);
+
+            if drop_through.ticks == 0 {
+                dropped.push(entity);
+            }
+        }
+
+        for entity in dropped {
+            drop_through_storage.remove(entity);
+        }
+
+        let gravity = physics_world.gravity();
+
+        for (body, gravity_scale, in_water, _) in (
+            &body_storage,
+            (&gravity_scale_storage).maybe(),
+            (&in_water_storage).maybe(),
+            !&controller_storage,
+        )
+            .join()
+        {
+            let mut scale = gravity_scale.map_or(1.0, |gravity_scale| gravity_scale.0);
+            let mut drag = 0.0;
+
+            if in_water.is_some() {
+                scale *= tuning.physics.water_gravity_scale;
+                drag = tuning.physics.water_drag;
+            }
+
+            if scale == 1.0 && drag == 0.0 {
+                continue;
+            }
+
+            if let Some(rigid_body) = physics_world.bodies.rigid_body_mut(body.rigid_body_handle) {
+                if rigid_body.status() == BodyStatus::Dynamic {
+                    // The physics world already applies gravity once.
+                    let mass = rigid_body.augmented_mass().linear;
+                    let extra_gravity = gravity * (scale - 1.0) * mass;
+                    let drag_force = -rigid_body.velocity().linear * drag * mass;
+                    rigid_body.apply_force(0, &Force2::linear(extra_gravity + drag_force), ForceType::Force, true);
+                }
+            }
+        }
+
+        for (body, feet_sensor, jumper, _) in (
+            &body_storage,
+            &feet_sensor_storage,
+            (&jumper_storage).maybe(),
+            !&controller_storage,
+        )
+            .join()
+        {
+            if !feet_sensor.on_floor || jumper.map_or(false, |jumper| jumper.jumping) {
+                continue;
+            }
+
+            // Cancel the pull of gravity along a slope, so grounded bodies don't slide down it.
+            let normal = feet_sensor.ground_normal;
+            let along_slope = gravity - normal * gravity.dot(&normal);
+
+            if along_slope != nphysics2d::nalgebra::Vector2::zeros() {
+                if let Some(rigid_body) = physics_world.bodies.rigid_body_mut(body.rigid_body_handle) {
+                    let mass = rigid_body.augmented_mass().linear;
+                    rigid_body.apply_force(0, &Force2::linear(-along_slope * mass), ForceType::Force, true);
+                }
+            }
+        }
+
         physics_world.step();
 
-        for (transform, body, animation, feet_sensor) in (
+        for (transform, body, animation, feet_sensor, jumper, killed, controller) in (
             &mut transform_storage,
             &body_storage,
             (&mut animation_storage).maybe(),
             (&mut feet_sensor_storage).maybe(),
+            (&mut jumper_storage).maybe(),
+            (&killed_storage).maybe(),
+            (&controller_storage).maybe(),
         )
             .join()
         {
             let rigid_body = physics_world.bodies.get(body.rigid_body_handle);
+            let mut inherited_velocity = nphysics2d::nalgebra::Vector2::zeros();
+            let mut ground_contact = None;
+
             if let Some(rigid_body) = rigid_body {
                 let part = rigid_body.part(0).unwrap();
                 let point = nphysics2d::nalgebra::Point2::new(0.0, 0.0);
@@ -248,52 +797,130 @@ impl<'a> System<'a> for PhysicsSystem {
                 transform.rotation = iso.rotation.angle();
 
                 let velocity = rigid_body.velocity_at_point(0, &point);
+                let mut relative_velocity_x = velocity.linear.x;
 
-                if let Some(animation) = animation {
-                    let on_floor = match feet_sensor {
-                        Some(feet) => {
-                            let mut feet_grounded = false;
-                            let contacts =
-                                physics_world.geometrical_world.colliders_interacting_with(
-                                    &physics_world.colliders,
-                                    feet.collider_handle,
-                                );
-
-                            for (_, collider) in contacts.unwrap() {
-                                if let Some(user_data) = collider.user_data() {
-                                    feet_grounded = match user_data.downcast_ref::<&str>() {
-                                        Some(&"brick") => true,
-                                        _ => false,
-                                    };
-
-                                    if feet_grounded {
-                                        break;
-                                    }
-                                }
+                let on_floor = match (feet_sensor, controller) {
+                    // Character controllers find their own ground while moving.
+                    (Some(feet), Some(controller)) => {
+                        relative_velocity_x = controller.velocity.x;
+                        feet.on_floor
+                    }
+                    (Some(feet), None) => {
+                        let mut ground = None;
+                        let contacts =
+                            physics_world.geometrical_world.colliders_interacting_with(
+                                &physics_world.colliders,
+                                feet.collider_handle,
+                            );
+
+                        for (collider_handle, collider) in contacts.unwrap() {
+                            if physics_world.passes_one_way(body.collider_handle, collider_handle) {
+                                continue;
                             }
 
-                            feet.on_floor = feet_grounded;
+                            if let Some(user_data) = collider.user_data() {
+                                let is_ground = match user_data.downcast_ref::<&str>() {
+                                    Some(&"brick") | Some(&"platform") | Some(&"slope") => true,
+                                    _ => false,
+                                };
+
+                                if is_ground {
+                                    let normal = physics_world
+                                        .colliders
+                                        .get(body.collider_handle)
+                                        .and_then(|own| {
+                                            query::contact(
+                                                own.position(),
+                                                own.shape(),
+                                                collider.position(),
+                                                collider.shape(),
+                                                GROUND_CONTACT_PREDICTION,
+                                            )
+                                        })
+                                        .map_or(nphysics2d::nalgebra::Vector2::y(), |contact| -contact.normal.into_inner());
 
-                            feet_grounded
+                                    ground = Some((collider.body(), normal));
+                                    break;
+                                }
+                            }
                         }
-                        None => false,
-                    };
-
-                    let drag_threshold = 10.0;
-
-                    if on_floor {
-                        if (velocity.linear.x < -drag_threshold && transform.scale.x > 0.0)
-                            || (velocity.linear.x > drag_threshold && transform.scale.x < 0.0)
-                        {
-                            animation.current_state = AnimationStates::Drag;
-                        } else if velocity.linear.x.abs() > 20.0 {
-                            animation.current_state = AnimationStates::Moving;
-                            animation.speed = animation.speed_factor * velocity.linear.x.abs();
-                        } else {
-                            animation.current_state = AnimationStates::Idle;
+
+                        match ground {
+                            Some((ground_handle, normal)) => {
+                                let ground_velocity = physics_world
+                                    .bodies
+                                    .rigid_body(ground_handle)
+                                    .map_or(nphysics2d::nalgebra::Vector2::zeros(), |ground_body| ground_body.velocity().linear);
+
+                                // Riders pick up any change in the velocity of what they stand on.
+                                inherited_velocity = ground_velocity - feet.ground_velocity;
+                                feet.ground_velocity = ground_velocity;
+                                feet.ground_normal = normal;
+                                relative_velocity_x -= ground_velocity.x;
+                                ground_contact = Some((ground_velocity, normal));
+                            }
+                            None => {
+                                feet.ground_velocity = nphysics2d::nalgebra::Vector2::zeros();
+                                feet.ground_normal = nphysics2d::nalgebra::Vector2::y();
+                            }
                         }
+
+                        feet.on_floor = ground.is_some();
+
+                        feet.on_floor
+                    }
+                    (None, _) => false,
+                };
+
+                if let Some(animation) = animation {
+                    let drag_threshold = tuning.animation.drag_threshold;
+                    let skidding = (relative_velocity_x < -drag_threshold && transform.scale.x > 0.0)
+                        || (relative_velocity_x > drag_threshold && transform.scale.x < 0.0);
+
+                    animation.parameters.set_bool("grounded", on_floor);
+                    animation.parameters.set_bool("skidding", on_floor && skidding);
+                    animation.parameters.set("speed", relative_velocity_x.abs());
+                }
+            }
+
+            let mut snap = ground_contact.is_some() && killed.is_none();
+
+            if let Some(jumper) = jumper {
+                if let Some((ground_velocity, _)) = ground_contact {
+                    let landed = physics_world
+                        .bodies
+                        .rigid_body(body.rigid_body_handle)
+                        .map_or(true, |rigid_body| rigid_body.velocity().linear.y <= ground_velocity.y);
+
+                    if landed {
+                        jumper.jumping = false;
+                    }
+                }
+
+                snap = snap && !jumper.jumping;
+            }
+
+            if let Some(rigid_body) = physics_world.bodies.rigid_body_mut(body.rigid_body_handle) {
+                let mut velocity = rigid_body.velocity().linear + inherited_velocity;
+
+                if let (true, Some((ground_velocity, normal))) = (snap, ground_contact) {
+                    let relative_velocity = velocity - ground_velocity;
+                    let tangent = nphysics2d::nalgebra::Vector2::new(normal.y, -normal.x);
+
+                    // Keep grounded bodies on the surface rather than launching them off crests or
+                    // down slopes, while keeping their horizontal speed.
+                    if relative_velocity.dot(&normal) > 0.0 && tangent.x.abs() > std::f32::EPSILON {
+                        velocity = ground_velocity + tangent * (relative_velocity.x / tangent.x);
                     }
                 }
+
+                if controller.is_none() {
+                    velocity.y = velocity.y.max(-tuning.physics.terminal_fall_speed);
+                }
+
+                if velocity != rigid_body.velocity().linear {
+                    rigid_body.set_linear_velocity(velocity);
+                }
             }
         }
     }
@@ -306,62 +933,1223 @@ impl<'a> System<'a> for CameraSystem {
         ReadStorage<'a, Transform>,
         Write<'a, Camera>,
         ReadStorage<'a, CameraTarget>,
+        ReadStorage<'a, CharacterController>,
+        ReadStorage<'a, FeetSensor>,
+        Read<'a, LevelBounds>,
+        Read<'a, InputEvents>,
     );
 
-    fn run(&mut self, (transform_storage, mut camera, camera_target_flag): Self::SystemData) {
-        for (transform, _) in (&transform_storage, &camera_target_flag).join() {
+    fn run(
+        &mut self,
+        (
+            transform_storage,
+            mut camera,
+            camera_target_flag,
+            controller_storage,
+            feet_sensor_storage,
+            bounds,
+            input_events,
+        ): Self::SystemData,
+    ) {
+        camera.set_bounds(*bounds);
+
+        // Whatever holds up the game can be skipped.
+        if camera.pauses_play() && input_events.is_key_pressed(&KeyCode::Return) {
+            camera.release();
+        }
+
+        for (transform, _, controller, feet_sensor) in (
+            &transform_storage,
+            &camera_target_flag,
+            (&controller_storage).maybe(),
+            (&feet_sensor_storage).maybe(),
+        )
+            .join()
+        {
             camera.set_target(&transform.position);
+            camera.set_target_motion(
+                controller.map_or(0.0, |controller| controller.velocity.x),
+                feet_sensor.map_or(true, |feet_sensor| feet_sensor.on_floor),
+            );
         }
+
+        camera.update(1.0 / TARGET_FPS as f32);
     }
 }
 
 pub struct AnimationSystem;
 
 impl<'a> System<'a> for AnimationSystem {
-    type SystemData = (WriteStorage<'a, Animation>, WriteStorage<'a, Sprite>);
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Animation>,
+        WriteStorage<'a, Sprite>,
+        Write<'a, AnimationEvents>,
+    );
 
-    fn run(&mut self, (mut animation_storage, mut sprite_storage): Self::SystemData) {
-        for (animation, sprite) in (&mut animation_storage, &mut sprite_storage).join() {
-            let current_state = animation.animations.get_mut(&animation.current_state);
+    fn run(&mut self, (entities, mut animation_storage, mut sprite_storage, mut animation_events): Self::SystemData) {
+        animation_events.0.clear();
 
-            if let Some(animation_params) = current_state {
-                animation_params.frame += (animation.speed * 1.0 / TARGET_FPS as f32) as f32;
-                sprite.src.x = sprite.src.w
-                    * ((animation_params.frame.floor() % animation_params.frame_count as f32)
-                    + animation_params.start_frame as f32);
+        for (entity, animation, sprite) in (&entities, &mut animation_storage, &mut sprite_storage).join() {
+            let graph = Arc::clone(&animation.graph);
 
-                if animation_params.frame >= (animation_params.frame_count + animation_params.start_frame) as f32 {
-                    animation_params.frame = animation_params.start_frame as f32;
-                }
+            let finished = animation
+                .animations
+                .get(&animation.current_state)
+                .map_or(false, |animation_params| animation_params.finished);
+
+            if let Some(next) = graph.next_state(&animation.current_state, &animation.parameters, finished) {
+                animation.current_state = next.to_string();
             }
-        }
-    }
-}
 
-pub struct EnemySystem;
+            let state = animation.current_state.clone();
+            let restart = animation.playing.as_ref() != Some(&state);
+            animation.playing = Some(state.clone());
 
-impl<'a> System<'a> for EnemySystem {
-    type SystemData = (ReadStorage<'a, BasicAI>, ReadStorage<'a, FeetSensor>, WriteStorage<'a, Body>, Write<'a, PhysicsWorld>);
+            let state_data = match graph.states.get(&state) {
+                Some(state_data) => state_data,
+                None => continue,
+            };
 
-    fn run(&mut self, (basic_ai_storage, feet_sensor_storage, mut body_storage, mut physics_world): Self::SystemData) {
-        let center_point = nphysics2d::nalgebra::Point2::new(0.0, 0.0);
-        let mut force = Force2::linear(nphysics2d::nalgebra::Vector2::new(0.0, 0.0));
-        let mut top_speed = 50.0;
-        let accel = (10000.0 * 1.0 / TARGET_FPS as f32) as f32;
+            let speed = state_data
+                .rate
+                .as_ref()
+                .map_or(1.0, |parameter| animation.speed_factor * animation.parameters.get(parameter));
 
+            if let Some(animation_params) = animation.animations.get_mut(&state) {
+                if restart {
+                    animation_params.restart();
+                }
 
-        for (feet_sensor, _, body) in (&feet_sensor_storage, &basic_ai_storage, &mut body_storage).join() {
+                let previous_frame = animation_params.frame;
 
-            let mut rigid_body = physics_world.bodies.get_mut(body.rigid_body_handle);
-            if let Some(rigid_body) = rigid_body {
-                let current_velocity = rigid_body.velocity_at_point(0, &center_point);
+                if animation_params.advance(speed / TARGET_FPS as f32) {
+                    animation_events.0.push(AnimationEvent::Finished { entity, state: state.clone() });
+                }
 
-                if current_velocity.linear.x < top_speed {
-                    force.linear.x = accel;
+                if restart || animation_params.frame != previous_frame {
+                    for name in state_data.events.get(&animation_params.frame).into_iter().flatten() {
+                        animation_events.0.push(AnimationEvent::Raised { entity, name: name.clone() });
+                    }
                 }
 
-                rigid_body.apply_force(0, &force, ForceType::Impulse, true);
+                if let Some(frame) = animation_params.clip.frames.get(animation_params.frame) {
+                    sprite.texture = frame.texture;
+                    sprite.src = frame.src;
+                }
             }
         }
     }
 }
+
+/// Kicks up a puff of dust at the feet of whatever raised a footstep in its animation.
+/// Runs after the animation system, which raises the footsteps.
+pub struct FootstepSystem;
+
+impl<'a> System<'a> for FootstepSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, AnimationEvents>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, Sprite>,
+        WriteStorage<'a, Dust>,
+        Read<'a, TextureIndex>,
+    );
+
+    fn run(
+        &mut self,
+        (entities, animation_events, mut transform_storage, mut sprite_storage, mut dust_storage, textures): Self::SystemData,
+    ) {
+        for (entity, dust, transform) in (&entities, &mut dust_storage, &mut transform_storage).join() {
+            if dust.ticks == 0 {
+                entities.delete(entity).expect("Failed to clear dust");
+                continue;
+            }
+
+            dust.ticks -= 1;
+
+            let scale = DUST_SCALE * dust.ticks as f32 / DUST_TICKS as f32;
+            transform.scale = nphysics2d::nalgebra::Vector2::new(scale, scale);
+            transform.position.y += DUST_RISE;
+        }
+
+        let footsteps: Vec<_> = animation_events
+            .0
+            .iter()
+            .filter_map(|event| match event {
+                AnimationEvent::Raised { entity, name } if name == "footstep" => transform_storage.get(*entity),
+                _ => None,
+            })
+            .map(|transform| Point2::new(transform.position.x, transform.position.y - FOOTSTEP_DROP))
+            .collect();
+
+        if footsteps.is_empty() {
+            return;
+        }
+
+        let frame = textures.frame("dust").expect("Missing sprite frame dust");
+
+        for position in footsteps {
+            entities
+                .build_entity()
+                .with(
+                    Sprite {
+                        texture: frame.texture,
+                        src: frame.src,
+                        width: 16f32,
+                        height: 16f32,
+                        repeat: nphysics2d::nalgebra::Vector2::new(1, 1),
+                    },
+                    &mut sprite_storage,
+                )
+                .with(
+                    Transform {
+                        position,
+                        rotation: 0.0,
+                        scale: nphysics2d::nalgebra::Vector2::new(DUST_SCALE, DUST_SCALE),
+                    },
+                    &mut transform_storage,
+                )
+                .with(Dust { ticks: DUST_TICKS }, &mut dust_storage)
+                .build();
+        }
+    }
+}
+
+pub struct EnemySystem;
+
+impl<'a> System<'a> for EnemySystem {
+    type SystemData = (
+        ReadStorage<'a, BasicAI>,
+        ReadStorage<'a, FeetSensor>,
+        WriteStorage<'a, Body>,
+        WriteStorage<'a, SwimAI>,
+        ReadStorage<'a, InWater>,
+        WriteStorage<'a, Transform>,
+        Write<'a, PhysicsWorld>,
+        Read<'a, Tuning>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            basic_ai_storage,
+            feet_sensor_storage,
+            mut body_storage,
+            mut swim_ai_storage,
+            in_water_storage,
+            mut transform_storage,
+            mut physics_world,
+            tuning,
+        ): Self::SystemData,
+    ) {
+        let center_point = nphysics2d::nalgebra::Point2::new(0.0, 0.0);
+        let mut force = Force2::linear(nphysics2d::nalgebra::Vector2::new(0.0, 0.0));
+        let mut top_speed = tuning.enemy.walk_speed;
+        let accel = (tuning.enemy.acceleration * 1.0 / TARGET_FPS as f32) as f32;
+
+
+        for (feet_sensor, _, body) in (&feet_sensor_storage, &basic_ai_storage, &mut body_storage).join() {
+
+            let mut rigid_body = physics_world.bodies.get_mut(body.rigid_body_handle);
+            if let Some(rigid_body) = rigid_body {
+                let current_velocity = rigid_body.velocity_at_point(0, &center_point);
+
+                if current_velocity.linear.x < top_speed {
+                    force.linear.x = accel;
+                }
+
+                rigid_body.apply_force(0, &force, ForceType::Impulse, true);
+            }
+        }
+
+        // Out of water swimmers are left to gravity, flopping around until they fall back in.
+        for (swim_ai, body, transform, _) in (&mut swim_ai_storage, &body_storage, &mut transform_storage, &in_water_storage).join() {
+            if let Some(rigid_body) = physics_world.bodies.rigid_body_mut(body.rigid_body_handle) {
+                swim_ai.ticks += 1;
+
+                // Turn around every so often, or straight away when swimming into a wall.
+                let blocked = swim_ai.ticks > 1 && rigid_body.velocity().linear.x * swim_ai.direction <= 0.0;
+
+                if swim_ai.ticks >= tuning.enemy.swim_turn_ticks || blocked {
+                    swim_ai.direction = -swim_ai.direction;
+                    swim_ai.ticks = 0;
+                }
+
+                let bob = (swim_ai.ticks as f32 * SWIM_BOB_RATE).sin() * tuning.enemy.swim_speed / 2.0;
+                rigid_body.set_linear_velocity(nphysics2d::nalgebra::Vector2::new(
+                    swim_ai.direction * tuning.enemy.swim_speed,
+                    bob,
+                ));
+
+                transform.scale.x = -swim_ai.direction * transform.scale.x.abs();
+            }
+        }
+    }
+}
+
+pub struct LevelTimerSystem;
+
+impl<'a> System<'a> for LevelTimerSystem {
+    type SystemData = (
+        Entities<'a>,
+        Write<'a, LevelTimer>,
+        ReadStorage<'a, Player>,
+        WriteStorage<'a, Killed>,
+    );
+
+    fn run(&mut self, (entities, mut level_timer, player_storage, mut killed_storage): Self::SystemData) {
+        let was_running = level_timer.is_running();
+
+        if level_timer.tick() == TimerState::Expired && was_running {
+            for (entity, _) in (&entities, &player_storage).join() {
+                killed_storage
+                    .insert(entity, Killed)
+                    .expect("Failed to kill player on time out");
+            }
+        }
+    }
+}
+
+pub struct DeathSystem;
+
+impl<'a> System<'a> for DeathSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Player>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, Body>,
+        WriteStorage<'a, Killed>,
+        WriteStorage<'a, DeathSequence>,
+        WriteStorage<'a, CharacterController>,
+        WriteStorage<'a, GravityScale>,
+        Write<'a, PhysicsWorld>,
+        Write<'a, LevelTimer>,
+        Write<'a, GameSession>,
+        Read<'a, LevelBounds>,
+        Write<'a, PendingTransition>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            player_storage,
+            transform_storage,
+            body_storage,
+            mut killed_storage,
+            mut death_sequence_storage,
+            mut controller_storage,
+            mut gravity_scale_storage,
+            mut physics_world,
+            mut level_timer,
+            mut session,
+            level_bounds,
+            mut pending_transition,
+        ): Self::SystemData,
+    ) {
+        for (entity, transform, _) in (&entities, &transform_storage, &player_storage).join() {
+            if transform.position.y < level_bounds.bottom {
+                killed_storage
+                    .insert(entity, Killed)
+                    .expect("Failed to kill player below the level");
+            }
+        }
+
+        for (entity, body, _, _) in (&entities, &body_storage, &player_storage, &killed_storage).join() {
+            if !death_sequence_storage.contains(entity) {
+                // Without a collider the player drops through the floor once the hop is over.
+                physics_world.remove_collider(body.collider_handle);
+                level_timer.stop();
+
+                death_sequence_storage
+                    .insert(entity, DeathSequence::default())
+                    .expect("Failed to start death sequence");
+            }
+
+            let sequence = death_sequence_storage.get_mut(entity).unwrap();
+            sequence.ticks += 1;
+
+            if let Some(controller) = controller_storage.get_mut(entity) {
+                if sequence.ticks < DEATH_FREEZE_TICKS {
+                    controller.gravity = false;
+                    controller.velocity = nphysics2d::nalgebra::Vector2::zeros();
+                } else if sequence.ticks == DEATH_FREEZE_TICKS {
+                    controller.gravity = true;
+                    controller.velocity = nphysics2d::nalgebra::Vector2::new(0.0, DEATH_HOP_VELOCITY);
+                }
+            }
+
+            if let Some(gravity_scale) = gravity_scale_storage.get_mut(entity) {
+                gravity_scale.0 = 1.0;
+            }
+
+            if sequence.ticks == DEATH_SEQUENCE_TICKS {
+                session.lives = session.lives.saturating_sub(1);
+                pending_transition.0 = Some(SceneTransition::Restart);
+            }
+        }
+    }
+}
+
+pub struct CheckpointSystem;
+
+impl<'a> System<'a> for CheckpointSystem {
+    type SystemData = (
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, Body>,
+        ReadStorage<'a, Player>,
+        WriteStorage<'a, Checkpoint>,
+        Read<'a, PhysicsWorld>,
+        Write<'a, GameSession>,
+        Read<'a, LevelInfo>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            transform_storage,
+            body_storage,
+            player_storage,
+            mut checkpoint_storage,
+            physics_world,
+            mut session,
+            level_info,
+        ): Self::SystemData,
+    ) {
+        for (transform, body, checkpoint) in (&transform_storage, &body_storage, &mut checkpoint_storage).join() {
+            if checkpoint.activated {
+                continue;
+            }
+
+            for (player_body, _) in (&body_storage, &player_storage).join() {
+                if physics_world.colliders_touching(body.collider_handle, player_body.collider_handle) {
+                    checkpoint.activated = true;
+                    session.checkpoint = Some(CheckpointState {
+                        area: level_info.area.clone(),
+                        position: transform.position,
+                    });
+                }
+            }
+        }
+    }
+}
+
+pub struct GoalSystem;
+
+impl<'a> System<'a> for GoalSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Goal>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, Body>,
+        ReadStorage<'a, Player>,
+        ReadStorage<'a, Killed>,
+        WriteStorage<'a, LevelComplete>,
+        WriteStorage<'a, Sprite>,
+        WriteStorage<'a, CharacterController>,
+        Read<'a, PhysicsWorld>,
+        Write<'a, LevelTimer>,
+        Write<'a, GameSession>,
+        Write<'a, PendingTransition>,
+        Write<'a, Camera>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            goal_storage,
+            transform_storage,
+            body_storage,
+            player_storage,
+            killed_storage,
+            mut level_complete_storage,
+            mut sprite_storage,
+            mut controller_storage,
+            physics_world,
+            mut level_timer,
+            mut session,
+            mut pending_transition,
+            mut camera,
+        ): Self::SystemData,
+    ) {
+        let mut reached = Vec::new();
+
+        for (goal, goal_body) in (&goal_storage, &body_storage).join() {
+            for (entity, transform, body, _, _, _) in (
+                &entities,
+                &transform_storage,
+                &body_storage,
+                &player_storage,
+                !&killed_storage,
+                !&level_complete_storage,
+            )
+                .join()
+            {
+                if physics_world.colliders_touching(goal_body.collider_handle, body.collider_handle) {
+                    let grab_height = (transform.position.y - goal.bottom) / goal.height;
+                    reached.push((entity, grab_height, goal.castle_x));
+                }
+            }
+        }
+
+        for (entity, grab_height, castle_x) in reached {
+            if level_complete_storage.contains(entity) {
+                continue;
+            }
+
+            let (_, points) = FLAGPOLE_SCORES
+                .iter()
+                .find(|(min_height, _)| grab_height >= *min_height)
+                .unwrap_or(&FLAGPOLE_SCORES[FLAGPOLE_SCORES.len() - 1]);
+
+            session.score += points;
+            level_timer.stop();
+            camera.zoom_to(GOAL_ZOOM);
+
+            level_complete_storage
+                .insert(
+                    entity,
+                    LevelComplete {
+                        phase: GoalPhase::Walk,
+                        castle_x,
+                    },
+                )
+                .expect("Failed to complete level");
+        }
+
+        for (entity, transform, controller, level_complete) in (
+            &entities,
+            &transform_storage,
+            &mut controller_storage,
+            &mut level_complete_storage,
+        )
+            .join()
+        {
+            match level_complete.phase {
+                GoalPhase::Walk => {
+                    if transform.position.x < level_complete.castle_x {
+                        controller.velocity.x = CASTLE_WALK_SPEED;
+                    } else {
+                        // Through the castle door.
+                        controller.velocity.x = 0.0;
+                        sprite_storage.remove(entity);
+                        level_complete.phase = GoalPhase::Tally;
+                    }
+                }
+                GoalPhase::Tally => {
+                    session.score += level_timer.tally(TALLY_SECONDS_PER_TICK);
+
+                    if level_timer.seconds_left() == 0 {
+                        level_complete.phase = GoalPhase::Done;
+                        pending_transition.0 = Some(SceneTransition::NextLevel);
+                    }
+                }
+                GoalPhase::Done => {}
+            }
+        }
+    }
+}
+
+pub struct PipeSystem;
+
+impl<'a> System<'a> for PipeSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Pipe>,
+        ReadStorage<'a, Body>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, Player>,
+        ReadStorage<'a, FeetSensor>,
+        ReadStorage<'a, Killed>,
+        WriteStorage<'a, PipeTransit>,
+        WriteStorage<'a, CharacterController>,
+        Read<'a, InputEvents>,
+        Write<'a, PhysicsWorld>,
+        Write<'a, PendingTransition>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            pipe_storage,
+            body_storage,
+            transform_storage,
+            player_storage,
+            feet_sensor_storage,
+            killed_storage,
+            mut pipe_transit_storage,
+            mut controller_storage,
+            input_events,
+            mut physics_world,
+            mut pending_transition,
+        ): Self::SystemData,
+    ) {
+        let mut entering = Vec::new();
+
+        for (pipe, pipe_body) in (&pipe_storage, &body_storage).join() {
+            let destination = match pipe.destination {
+                Some(ref destination) => destination,
+                None => continue,
+            };
+
+            let pressed = match pipe.direction {
+                PipeDirection::Down => {
+                    input_events.is_key_pressed(&KeyCode::S) || input_events.is_key_pressed(&KeyCode::Down)
+                }
+                PipeDirection::Up => {
+                    input_events.is_key_pressed(&KeyCode::W) || input_events.is_key_pressed(&KeyCode::Up)
+                }
+                PipeDirection::Left => {
+                    input_events.is_key_pressed(&KeyCode::A) || input_events.is_key_pressed(&KeyCode::Left)
+                }
+                PipeDirection::Right => {
+                    input_events.is_key_pressed(&KeyCode::D) || input_events.is_key_pressed(&KeyCode::Right)
+                }
+            };
+
+            if !pressed {
+                continue;
+            }
+
+            for (entity, transform, body, feet_sensor, _, _, _) in (
+                &entities,
+                &transform_storage,
+                &body_storage,
+                &feet_sensor_storage,
+                &player_storage,
+                !&killed_storage,
+                !&pipe_transit_storage,
+            )
+                .join()
+            {
+                let lined_up = match pipe.direction {
+                    PipeDirection::Down => {
+                        feet_sensor.on_floor
+                            && physics_world.colliders_touching(feet_sensor.collider_handle, pipe_body.collider_handle)
+                            && (transform.position.x - pipe.opening.x).abs() < PIPE_ENTER_TOLERANCE
+                    }
+                    PipeDirection::Up => {
+                        physics_world.colliders_touching(body.collider_handle, pipe_body.collider_handle)
+                            && (transform.position.x - pipe.opening.x).abs() < PIPE_ENTER_TOLERANCE
+                    }
+                    PipeDirection::Left | PipeDirection::Right => {
+                        feet_sensor.on_floor
+                            && physics_world.colliders_touching(body.collider_handle, pipe_body.collider_handle)
+                            && (transform.position.y - pipe.opening.y).abs() < PIPE_ENTER_TOLERANCE
+                    }
+                };
+
+                if lined_up {
+                    entering.push((entity, *body, pipe.direction, pipe.opening, destination.clone()));
+                }
+            }
+        }
+
+        for (entity, body, direction, opening, destination) in entering {
+            if pipe_transit_storage.contains(entity) {
+                continue;
+            }
+
+            // The player slides through the pipe walls, so it loses its collider like on death.
+            physics_world.remove_collider(body.collider_handle);
+
+            if let Some(rigid_body) = physics_world.bodies.rigid_body_mut(body.rigid_body_handle) {
+                let mut position = *rigid_body.position();
+
+                match direction {
+                    PipeDirection::Down | PipeDirection::Up => position.translation.vector.x = opening.x,
+                    PipeDirection::Left | PipeDirection::Right => {}
+                }
+
+                rigid_body.set_position(position);
+            }
+
+            if let Some(controller) = controller_storage.get_mut(entity) {
+                controller.gravity = false;
+            }
+
+            pipe_transit_storage
+                .insert(
+                    entity,
+                    PipeTransit {
+                        direction,
+                        destination,
+                        ticks: 0,
+                    },
+                )
+                .expect("Failed to enter pipe");
+        }
+
+        for (controller, transit) in (&mut controller_storage, &mut pipe_transit_storage).join() {
+            transit.ticks += 1;
+            controller.velocity = transit.direction.vector() * PIPE_ENTER_SPEED;
+
+            if transit.ticks == PIPE_ENTER_TICKS {
+                pending_transition.0 = Some(SceneTransition::Warp(transit.destination.clone()));
+            }
+        }
+    }
+}
+
+/// Picks up carryable objects while run is held, throwing them once it's let go.
+pub struct CarrySystem;
+
+impl<'a> System<'a> for CarrySystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, InputEvents>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, Body>,
+        ReadStorage<'a, CharacterController>,
+        ReadStorage<'a, FeetSensor>,
+        WriteStorage<'a, Carrier>,
+        ReadStorage<'a, Carryable>,
+        ReadStorage<'a, Killed>,
+        ReadStorage<'a, PipeTransit>,
+        Write<'a, PhysicsWorld>,
+        Read<'a, Tuning>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            input_events,
+            transform_storage,
+            body_storage,
+            controller_storage,
+            feet_sensor_storage,
+            mut carrier_storage,
+            carryable_storage,
+            killed_storage,
+            pipe_transit_storage,
+            mut physics_world,
+            tuning,
+        ): Self::SystemData,
+    ) {
+        let movement = &tuning.movement;
+        let running = input_events.is_mod_active(KeyMods::SHIFT);
+        let up_pressed = input_events.is_key_pressed(&KeyCode::W) || input_events.is_key_pressed(&KeyCode::Up);
+        let down_pressed = input_events.is_key_pressed(&KeyCode::S) || input_events.is_key_pressed(&KeyCode::Down);
+
+        for (entity, transform, body, controller, feet_sensor, carrier) in (
+            &entities,
+            &transform_storage,
+            &body_storage,
+            &controller_storage,
+            &feet_sensor_storage,
+            &mut carrier_storage,
+        )
+            .join()
+        {
+            let facing = transform.scale.x.signum();
+            let momentum = controller.velocity + feet_sensor.ground_velocity;
+            let incapacitated = killed_storage.contains(entity) || pipe_transit_storage.contains(entity);
+
+            if let Some(held) = carrier.held {
+                let held_body = match body_storage.get(held) {
+                    Some(held_body) if entities.is_alive(held) => held_body,
+                    _ => {
+                        carrier.held = None;
+                        continue;
+                    }
+                };
+
+                if running && !incapacitated {
+                    physics_world.carry(body, held_body, nphysics2d::nalgebra::Vector2::new(facing * CARRY_DISTANCE, 0.0));
+                    continue;
+                }
+
+                // Letting go throws up, sets down, or kicks forward, always carrying the player's momentum.
+                let velocity = if incapacitated || down_pressed {
+                    momentum
+                } else if up_pressed {
+                    nphysics2d::nalgebra::Vector2::new(momentum.x, movement.throw_up_velocity)
+                } else {
+                    nphysics2d::nalgebra::Vector2::new(
+                        momentum.x + facing * movement.throw_speed,
+                        momentum.y.max(0.0) + movement.throw_lift,
+                    )
+                };
+
+                physics_world.release(held_body, velocity);
+                carrier.held = None;
+            } else if running && !incapacitated {
+                let picked_up = (&entities, &body_storage, &carryable_storage)
+                    .join()
+                    .find(|(_, carryable_body, _)| {
+                        physics_world.colliders_touching(body.collider_handle, carryable_body.collider_handle)
+                    });
+
+                if let Some((carryable, carryable_body, _)) = picked_up {
+                    physics_world.carry(body, carryable_body, nphysics2d::nalgebra::Vector2::new(facing * CARRY_DISTANCE, 0.0));
+                    carrier.held = Some(carryable);
+                }
+            }
+        }
+    }
+}
+
+pub struct SpecialMoveSystem;
+
+impl<'a> System<'a> for SpecialMoveSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Body>,
+        ReadStorage<'a, Player>,
+        ReadStorage<'a, SpecialMove>,
+        WriteStorage<'a, FeetSensor>,
+        WriteStorage<'a, CharacterController>,
+        WriteStorage<'a, Jumper>,
+        ReadStorage<'a, Breakable>,
+        ReadStorage<'a, Spiky>,
+        WriteStorage<'a, Killed>,
+        Write<'a, PhysicsWorld>,
+        Write<'a, Camera>,
+        Read<'a, Tuning>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            body_storage,
+            player_storage,
+            special_move_storage,
+            mut feet_sensor_storage,
+            mut controller_storage,
+            mut jumper_storage,
+            breakable_storage,
+            spiky_storage,
+            mut killed_storage,
+            mut physics_world,
+            mut camera,
+            tuning,
+        ): Self::SystemData,
+    ) {
+        let mut broken = Vec::new();
+        let mut stomped = Vec::new();
+
+        for (entity, special_move, feet_sensor, controller, jumper, _, _) in (
+            &entities,
+            &special_move_storage,
+            &mut feet_sensor_storage,
+            &mut controller_storage,
+            (&mut jumper_storage).maybe(),
+            &player_storage,
+            !&killed_storage,
+        )
+            .join()
+        {
+            let smashing = match *special_move {
+                SpecialMove::SpinJump => true,
+                SpecialMove::GroundPound { ticks } => ticks > tuning.movement.ground_pound_hang_ticks,
+                SpecialMove::WallSlide { .. } | SpecialMove::Climb | SpecialMove::None => false,
+            };
+
+            // Only coming down on a block breaks it, not grazing its side on the way up.
+            if smashing && controller.velocity.y <= 0.0 {
+                for (block, block_body, _) in (&entities, &body_storage, &breakable_storage).join() {
+                    if physics_world.colliders_touching(feet_sensor.collider_handle, block_body.collider_handle) {
+                        broken.push((block, *block_body));
+                        // Keeps falling through whatever is below.
+                        feet_sensor.on_floor = false;
+                    }
+                }
+            }
+
+            if let (SpecialMove::GroundPound { .. }, true, true) = (*special_move, smashing, feet_sensor.on_floor) {
+                camera.shake(GROUND_POUND_SHAKE, GROUND_POUND_SHAKE_HALF_LIFE);
+            }
+
+            if controller.velocity.y > 0.0 {
+                continue;
+            }
+
+            let landed_on_spikes = (&body_storage, &spiky_storage)
+                .join()
+                .any(|(enemy_body, _)| {
+                    physics_world.colliders_touching(feet_sensor.collider_handle, enemy_body.collider_handle)
+                });
+
+            if landed_on_spikes {
+                if let SpecialMove::SpinJump = *special_move {
+                    controller.velocity.y = tuning.movement.spin_bounce_velocity;
+                    feet_sensor.on_floor = false;
+
+                    if let Some(jumper) = jumper {
+                        jumper.jumping = true;
+                    }
+                } else {
+                    stomped.push(entity);
+                }
+            }
+        }
+
+        for (block, block_body) in broken {
+            physics_world.remove_body(&block_body);
+            entities.delete(block).expect("Failed to break block");
+        }
+
+        for entity in stomped {
+            killed_storage
+                .insert(entity, Killed)
+                .expect("Failed to kill player on spikes");
+        }
+    }
+}
+
+/// Where a platform following `path` from `origin` should be after `time` seconds.
+fn platform_position(path: PlatformPath, origin: Point2<f32>, time: f32) -> Point2<f32> {
+    match path {
+        PlatformPath::Linear { dx, dy, speed } => {
+            let length = (dx * dx + dy * dy).sqrt();
+            let progress = if length > 0.0 { (speed * time / length).min(1.0) } else { 0.0 };
+
+            Point2::new(origin.x + dx * progress, origin.y + dy * progress)
+        }
+        PlatformPath::BackAndForth { dx, dy, speed } => {
+            let length = (dx * dx + dy * dy).sqrt();
+            let progress = if length > 0.0 {
+                let distance = (speed * time) % (2.0 * length);
+                let distance = if distance > length { 2.0 * length - distance } else { distance };
+
+                distance / length
+            } else {
+                0.0
+            };
+
+            Point2::new(origin.x + dx * progress, origin.y + dy * progress)
+        }
+        PlatformPath::Circular { radius, speed } => {
+            // Centered to the left of the origin, so the platform starts out where it was placed.
+            let angle = speed * time;
+            Point2::new(origin.x - radius + radius * angle.cos(), origin.y + radius * angle.sin())
+        }
+        PlatformPath::Falling { .. } => origin,
+    }
+}
+
+/// Flags the bodies that are inside water zones, from the physics world's sensor volume events.
+pub struct WaterSystem;
+
+impl<'a> System<'a> for WaterSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Body>,
+        WriteStorage<'a, InWater>,
+        Read<'a, PhysicsWorld>,
+    );
+
+    fn run(&mut self, (entities, body_storage, mut in_water_storage, physics_world): Self::SystemData) {
+        let changed: Vec<_> = physics_world
+            .volume_events()
+            .iter()
+            .filter_map(|event| match *event {
+                VolumeEvent::Entered { volume, collider } | VolumeEvent::Left { volume, collider } => {
+                    Some(collider).filter(|_| physics_world.collider_tag(volume) == Some("water"))
+                }
+            })
+            .collect();
+
+        if changed.is_empty() {
+            return;
+        }
+
+        for (entity, body) in (&entities, &body_storage).join() {
+            if !changed.contains(&body.collider_handle) {
+                continue;
+            }
+
+            let in_water = physics_world
+                .volumes_containing(body.collider_handle)
+                .any(|volume| physics_world.collider_tag(volume) == Some("water"));
+
+            if in_water {
+                in_water_storage.insert(entity, InWater).expect("Failed to put body in water");
+            } else {
+                in_water_storage.remove(entity);
+            }
+        }
+    }
+}
+
+pub struct MovingPlatformSystem;
+
+impl<'a> System<'a> for MovingPlatformSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Body>,
+        ReadStorage<'a, FeetSensor>,
+        ReadStorage<'a, Player>,
+        WriteStorage<'a, MovingPlatform>,
+        Write<'a, PhysicsWorld>,
+        Read<'a, LevelBounds>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            body_storage,
+            feet_sensor_storage,
+            player_storage,
+            mut platform_storage,
+            mut physics_world,
+            level_bounds,
+        ): Self::SystemData,
+    ) {
+        let delta = 1.0 / TARGET_FPS as f32;
+        let mut fallen = Vec::new();
+
+        for (entity, body, platform) in (&entities, &body_storage, &mut platform_storage).join() {
+            if let PlatformPath::Falling { .. } = platform.path {
+                // Only the player sets a platform off, enemies walking over it leave it be.
+                if !platform.triggered {
+                    platform.triggered = (&feet_sensor_storage, &player_storage)
+                        .join()
+                        .any(|(feet, _)| physics_world.colliders_touching(feet.collider_handle, body.collider_handle));
+                }
+
+                if !platform.triggered {
+                    continue;
+                }
+            }
+
+            platform.ticks += 1;
+
+            let rigid_body = match physics_world.bodies.rigid_body_mut(body.rigid_body_handle) {
+                Some(rigid_body) => rigid_body,
+                None => continue,
+            };
+
+            // Kinematic bodies are moved through their velocity so riders can pick it up.
+            let velocity = match platform.path {
+                PlatformPath::Falling { .. } if rigid_body.position().translation.vector.y < level_bounds.bottom => {
+                    fallen.push((entity, *body));
+                    continue;
+                }
+                PlatformPath::Falling { delay } => {
+                    if platform.ticks <= delay {
+                        nphysics2d::nalgebra::Vector2::zeros()
+                    } else {
+                        let fall_time = (platform.ticks - delay) as f32 * delta;
+                        let speed = (PLATFORM_FALL_ACCELERATION * fall_time).min(PLATFORM_MAX_FALL_SPEED);
+                        nphysics2d::nalgebra::Vector2::new(0.0, -speed)
+                    }
+                }
+                path => {
+                    let target = platform_position(path, platform.origin, platform.ticks as f32 * delta);
+                    let current = rigid_body.position().translation.vector;
+
+                    (nphysics2d::nalgebra::Vector2::new(target.x, target.y) - current) / delta
+                }
+            };
+
+            rigid_body.set_linear_velocity(velocity);
+        }
+
+        for (platform, platform_body) in fallen {
+            physics_world.remove_body(&platform_body);
+            entities.delete(platform).expect("Failed to remove fallen platform");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use nphysics2d::nalgebra::Vector2 as PhysicsVector2;
+    use nphysics2d::ncollide2d::shape::{Ball, Cuboid, ShapeHandle};
+    use nphysics2d::object::RigidBodyDesc;
+
+    use super::*;
+
+    /// A world with the player standing on a platform, either one-way or solid.
+    fn player_on_platform(one_way: bool) -> (World, Entity) {
+        let mut world = World::new();
+        System::setup(&mut PlayerControlSystem, &mut world);
+
+        let mut physics_world = PhysicsWorld::default();
+
+        // Platform with its top at 8, the player's ball resting on it.
+        let platform = RigidBodyDesc::new().status(BodyStatus::Static).build();
+        let mut platform_desc = ColliderDesc::new(ShapeHandle::new(Cuboid::new(PhysicsVector2::new(32.0, 8.0))));
+
+        if one_way {
+            platform_desc = platform_desc.collision_groups(PhysicsWorld::one_way_groups());
+        }
+
+        physics_world.insert_body(platform, platform_desc);
+
+        let player = RigidBodyDesc::new()
+            .translation(PhysicsVector2::new(0.0, 16.0))
+            .status(BodyStatus::Kinematic)
+            .build();
+        let body = physics_world.insert_body(player, ColliderDesc::new(ShapeHandle::new(Ball::new(8.0))));
+        let feet_desc = ColliderDesc::new(ShapeHandle::new(Cuboid::new(PhysicsVector2::new(7.0, 1.0))))
+            .translation(PhysicsVector2::new(0.0, -10.0));
+        let feet = physics_world.insert_sensor(&body.rigid_body_handle, feet_desc);
+
+        // Places the colliders where their bodies are.
+        physics_world.step();
+        world.insert(physics_world);
+
+        let entity = world
+            .create_entity()
+            .with(Transform {
+                position: Point2::new(0.0, 16.0),
+                rotation: 0.0,
+                scale: PhysicsVector2::new(1.0, 1.0),
+            })
+            .with(body)
+            .with(Player::default())
+            .with(CharacterController::default())
+            .with(FeetSensor {
+                collider_handle: feet,
+                on_floor: true,
+                ground_velocity: PhysicsVector2::zeros(),
+                ground_normal: PhysicsVector2::y(),
+            })
+            .with(Jumper::default())
+            .with(SpecialMove::default())
+            .build();
+
+        (world, entity)
+    }
+
+    /// Runs a tick of player control with `keys` held, the player standing on the ground or not.
+    fn tick(world: &mut World, entity: Entity, keys: &[KeyCode], on_floor: bool) {
+        world.write_resource::<InputEvents>().pressed_keys = keys.iter().map(|&key| (key, Instant::now())).collect();
+        world.write_storage::<FeetSensor>().get_mut(entity).unwrap().on_floor = on_floor;
+
+        PlayerControlSystem.run_now(world);
+        world.maintain();
+    }
+
+    fn ground_pounding(world: &World, entity: Entity) -> bool {
+        matches!(world.read_storage::<SpecialMove>().get(entity), Some(SpecialMove::GroundPound { .. }))
+    }
+
+    #[test]
+    fn holding_down_after_dropping_through_does_not_ground_pound() {
+        let (mut world, player) = player_on_platform(true);
+
+        tick(&mut world, player, &[KeyCode::S, KeyCode::Space], true);
+
+        assert!(world.read_storage::<DropThrough>().contains(player));
+        assert!(!world.read_storage::<Jumper>().get(player).unwrap().jumping);
+
+        // Falling through with down still held.
+        tick(&mut world, player, &[KeyCode::S, KeyCode::Space], false);
+        tick(&mut world, player, &[KeyCode::S], false);
+
+        assert!(!ground_pounding(&world, player));
+
+        // Pressing it again does.
+        tick(&mut world, player, &[], false);
+        tick(&mut world, player, &[KeyCode::S], false);
+
+        assert!(ground_pounding(&world, player));
+    }
+
+    #[test]
+    fn down_and_jump_on_solid_ground_jumps() {
+        let (mut world, player) = player_on_platform(false);
+
+        tick(&mut world, player, &[KeyCode::S, KeyCode::Space], true);
+
+        assert!(!world.read_storage::<DropThrough>().contains(player));
+        assert!(world.read_storage::<Jumper>().get(player).unwrap().jumping);
+    }
+
+    /// A world with something standing on a falling platform, the player or an enemy.
+    fn standing_on_falling_platform(player: bool) -> (World, Entity) {
+        let mut world = World::new();
+        System::setup(&mut MovingPlatformSystem, &mut world);
+
+        let mut physics_world = PhysicsWorld::default();
+
+        let platform = RigidBodyDesc::new().status(BodyStatus::Kinematic).build();
+        let platform_body =
+            physics_world.insert_body(platform, ColliderDesc::new(ShapeHandle::new(Cuboid::new(PhysicsVector2::new(16.0, 4.0)))));
+
+        let rider = RigidBodyDesc::new()
+            .translation(PhysicsVector2::new(0.0, 12.0))
+            .status(BodyStatus::Kinematic)
+            .build();
+        let rider_body = physics_world.insert_body(rider, ColliderDesc::new(ShapeHandle::new(Ball::new(8.0))));
+        let feet_desc = ColliderDesc::new(ShapeHandle::new(Cuboid::new(PhysicsVector2::new(7.0, 1.0))))
+            .translation(PhysicsVector2::new(0.0, -8.0));
+        let feet = physics_world.insert_sensor(&rider_body.rigid_body_handle, feet_desc);
+
+        physics_world.step();
+        world.insert(physics_world);
+        world.insert(LevelBounds {
+            left: -64.0,
+            right: 64.0,
+            bottom: -64.0,
+            top: 64.0,
+        });
+
+        let platform = world
+            .create_entity()
+            .with(platform_body)
+            .with(MovingPlatform {
+                path: PlatformPath::Falling { delay: 0 },
+                origin: Point2::new(0.0, 0.0),
+                ticks: 0,
+                triggered: false,
+            })
+            .build();
+
+        let rider = world.create_entity().with(rider_body).with(FeetSensor {
+            collider_handle: feet,
+            on_floor: true,
+            ground_velocity: PhysicsVector2::zeros(),
+            ground_normal: PhysicsVector2::y(),
+        });
+
+        if player {
+            rider.with(Player::default()).build();
+        } else {
+            rider.build();
+        }
+
+        (world, platform)
+    }
+
+    fn triggered(world: &World, platform: Entity) -> bool {
+        world.read_storage::<MovingPlatform>().get(platform).unwrap().triggered
+    }
+
+    #[test]
+    fn only_the_player_sets_off_falling_platforms() {
+        let (world, platform) = standing_on_falling_platform(false);
+        MovingPlatformSystem.run_now(&world);
+
+        assert!(!triggered(&world, platform));
+
+        let (world, platform) = standing_on_falling_platform(true);
+        MovingPlatformSystem.run_now(&world);
+
+        assert!(triggered(&world, platform));
+    }
+
+    #[test]
+    fn platforms_fallen_below_the_level_are_removed() {
+        let (mut world, platform) = standing_on_falling_platform(true);
+        let body = *world.read_storage::<Body>().get(platform).unwrap();
+
+        world.write_resource::<LevelBounds>().bottom = 16.0;
+        MovingPlatformSystem.run_now(&world);
+        world.maintain();
+
+        assert!(!world.is_alive(platform));
+        assert!(world.read_resource::<PhysicsWorld>().bodies.get(body.rigid_body_handle).is_none());
+    }
+}